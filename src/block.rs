@@ -63,6 +63,12 @@ impl Block {
         self.block.clone()
     }
 
+    /// Returns true if the contents of this block are also referenced by another Block or by an
+    /// iterator.
+    pub fn is_pinned(&self) -> bool {
+        Rc::strong_count(&self.block) > 1
+    }
+
     pub fn new(opt: Options, contents: BlockContents) -> Block {
         assert!(contents.len() > 4);
         Block {
@@ -1,4 +1,8 @@
+use crate::block::Block;
+
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::mem::swap;
 
 // No clone, no copy! That asserts that an LRUHandle exists only once.
@@ -93,6 +97,9 @@ impl<T> LRUList<T> {
             if current.next.is_some() {
                 // Update next node's predecessor.
                 current.next.as_mut().unwrap().prev = current.prev.take();
+            } else {
+                // Removing the last node; update head's reference to the last node.
+                self.head.prev = Some(prev);
             }
             (*prev).next = current.next.take();
 
@@ -150,29 +157,263 @@ impl<T> LRUList<T> {
 
 pub type CacheKey = [u8; 16];
 pub type CacheID = u64;
-type CacheEntry<T> = (T, LRUHandle<CacheKey>);
 
-/// Implementation of `ShardedLRUCache`.
-/// Based on a HashMap; the elements are linked in order to support the LRU ordering.
-pub struct Cache<T> {
+/// Priority determines the order of eviction: High-priority elements are only evicted once
+/// there are no low-priority elements left.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Priority {
+    Low,
+    High,
+}
+
+/// EvictionPolicy determines which low-priority elements are evicted first.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used element.
+    Lru,
+    /// Segmented LRU: New elements are evicted first, unless they have been looked up again
+    /// before. Elements that were looked up at least twice are only evicted once all others are
+    /// gone, so that a scan of many elements that are used once doesn't replace often used
+    /// ones.
+    SegmentedLru,
+}
+
+/// The share of a shard's capacity (in percent) that elements promoted by the segmented LRU
+/// policy may take up.
+const PROTECTED_PERCENT: usize = 80;
+
+/// The list an element is in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Segment {
+    /// Low-priority elements; with segmented LRU, those that haven't been looked up since
+    /// insertion ("probationary").
+    Low,
+    /// Low-priority elements that have been looked up since insertion, with segmented LRU.
+    Protected,
+    High,
+}
+
+struct CacheEntry<T> {
+    elem: T,
+    // None while the element is pinned.
+    lru_handle: Option<LRUHandle<CacheKey>>,
+    charge: usize,
+    segment: Segment,
+    pins: usize,
+}
+
+/// One shard of a Cache: A HashMap whose elements are linked in order to support the LRU
+/// ordering. Every segment has its own list; pinned elements are in none of them.
+struct LRUShard<T> {
     // note: CacheKeys (Vec<u8>) are duplicated between list and map. If this turns out to be a
     // performance bottleneck, another layer of indirection™ can solve this by mapping the key
     // to a numeric handle that keys both list and map.
-    list: LRUList<CacheKey>,
+    low: LRUList<CacheKey>,
+    protected: LRUList<CacheKey>,
+    high: LRUList<CacheKey>,
     map: HashMap<CacheKey, CacheEntry<T>>,
+    policy: EvictionPolicy,
+    cap: usize,
+    usage: usize,
+    protected_usage: usize,
+}
+
+impl<T> LRUShard<T> {
+    fn new(capacity: usize, policy: EvictionPolicy) -> LRUShard<T> {
+        LRUShard {
+            low: LRUList::new(),
+            protected: LRUList::new(),
+            high: LRUList::new(),
+            map: HashMap::with_capacity(1024),
+            policy,
+            cap: capacity,
+            usage: 0,
+            protected_usage: 0,
+        }
+    }
+
+    fn list(&mut self, segment: Segment) -> &mut LRUList<CacheKey> {
+        match segment {
+            Segment::Low => &mut self.low,
+            Segment::Protected => &mut self.protected,
+            Segment::High => &mut self.high,
+        }
+    }
+
+    /// Evict the least recently used elements until `charge` more fits into the shard. Pinned
+    /// elements are never evicted, so the usage may stay above the capacity.
+    fn make_room(&mut self, charge: usize) {
+        while self.usage + charge > self.cap {
+            let removed_key = match self.low.remove_last() {
+                Some(k) => k,
+                None => match self.protected.remove_last() {
+                    Some(k) => k,
+                    None => match self.high.remove_last() {
+                        Some(k) => k,
+                        None => break,
+                    },
+                },
+            };
+            let removed = self.map.remove(&removed_key).unwrap();
+            self.usage -= removed.charge;
+            if removed.segment == Segment::Protected {
+                self.protected_usage -= removed.charge;
+            }
+        }
+    }
+
+    fn insert(&mut self, key: &CacheKey, elem: T, charge: usize, priority: Priority) {
+        self.remove(key);
+        // An element larger than the shard replaces all others.
+        self.make_room(charge);
+        let segment = match priority {
+            Priority::Low => Segment::Low,
+            Priority::High => Segment::High,
+        };
+        let lru_handle = self.list(segment).insert(*key);
+        let entry = CacheEntry {
+            elem,
+            lru_handle: Some(lru_handle),
+            charge,
+            segment,
+            pins: 0,
+        };
+        self.map.insert(*key, entry);
+        self.usage += charge;
+    }
+
+    /// Move an element that has been looked up from the probationary to the protected segment,
+    /// and the least recently used protected elements back while the segment is too large.
+    fn promote(&mut self, key: &CacheKey) {
+        let e = self.map.get_mut(key).unwrap();
+        e.segment = Segment::Protected;
+        let (lru_handle, charge) = (e.lru_handle.take(), e.charge);
+        if let Some(h) = lru_handle {
+            self.low.remove(h);
+            let h = self.protected.insert(*key);
+            self.map.get_mut(key).unwrap().lru_handle = Some(h);
+        }
+        self.protected_usage += charge;
+
+        while self.protected_usage > self.cap * PROTECTED_PERCENT / 100 {
+            let demoted_key = match self.protected.remove_last() {
+                Some(k) => k,
+                None => break,
+            };
+            let h = self.low.insert(demoted_key);
+            let e = self.map.get_mut(&demoted_key).unwrap();
+            e.segment = Segment::Low;
+            e.lru_handle = Some(h);
+            self.protected_usage -= e.charge;
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<&T> {
+        let (lru_handle, segment) = match self.map.get(key) {
+            None => return None,
+            Some(e) => (e.lru_handle, e.segment),
+        };
+        if segment == Segment::Low && self.policy == EvictionPolicy::SegmentedLru {
+            self.promote(key);
+        } else if let Some(h) = lru_handle {
+            self.list(segment).reinsert_front(h);
+        }
+        self.map.get(key).map(|e| &e.elem)
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> Option<T> {
+        match self.map.remove(key) {
+            None => None,
+            Some(e) => {
+                if let Some(h) = e.lru_handle {
+                    self.list(e.segment).remove(h);
+                }
+                self.usage -= e.charge;
+                if e.segment == Segment::Protected {
+                    self.protected_usage -= e.charge;
+                }
+                Some(e.elem)
+            }
+        }
+    }
+
+    fn pin(&mut self, key: &CacheKey) -> bool {
+        let (lru_handle, segment) = match self.map.get_mut(key) {
+            None => return false,
+            Some(e) => {
+                e.pins += 1;
+                (e.lru_handle.take(), e.segment)
+            }
+        };
+        if let Some(h) = lru_handle {
+            self.list(segment).remove(h);
+        }
+        true
+    }
+
+    fn unpin(&mut self, key: &CacheKey) {
+        let segment = match self.map.get_mut(key) {
+            Some(e) if e.pins > 0 => {
+                e.pins -= 1;
+                if e.pins > 0 {
+                    return;
+                }
+                e.segment
+            }
+            _ => return,
+        };
+        let lru_handle = self.list(segment).insert(*key);
+        self.map.get_mut(key).unwrap().lru_handle = Some(lru_handle);
+        self.make_room(0);
+    }
+}
+
+/// Implementation of `ShardedLRUCache`.
+/// Every element is charged against the capacity, by default with 1 (so that the capacity is a
+/// number of elements); `insert_with_charge()` allows e.g. charging blocks by their size. Keys are
+/// distributed among shards by their hash, each of which holds an equal part of the capacity and
+/// evicts elements independently, according to the cache's EvictionPolicy.
+pub struct Cache<T> {
+    shards: Vec<LRUShard<T>>,
+    policy: EvictionPolicy,
     cap: usize,
     id: u64,
 }
 
 impl<T> Cache<T> {
+    /// Create a cache with a single shard.
     pub fn new(capacity: usize) -> Cache<T> {
+        Cache::new_sharded(capacity, 0)
+    }
+
+    /// Create a cache with `1 << shard_bits` shards.
+    pub fn new_sharded(capacity: usize, shard_bits: usize) -> Cache<T> {
+        Cache::new_with_policy(capacity, shard_bits, EvictionPolicy::Lru)
+    }
+
+    /// Create a cache with `1 << shard_bits` shards, evicting elements according to `policy`.
+    pub fn new_with_policy(capacity: usize, shard_bits: usize, policy: EvictionPolicy) -> Cache<T> {
         assert!(capacity > 0);
-        Cache {
-            list: LRUList::new(),
-            map: HashMap::with_capacity(1024),
-            cap: capacity,
+        assert!(shard_bits < 16);
+        let num_shards = 1 << shard_bits;
+        let mut cache = Cache {
+            shards: (0..num_shards).map(|_| LRUShard::new(0, policy)).collect(),
+            policy,
+            cap: 0,
             id: 0,
+        };
+        cache.set_capacity(capacity);
+        cache
+    }
+
+    fn shard(&mut self, key: &CacheKey) -> &mut LRUShard<T> {
+        let n = self.shards.len();
+        if n == 1 {
+            return &mut self.shards[0];
         }
+        let mut h = DefaultHasher::new();
+        key.hash(&mut h);
+        &mut self.shards[h.finish() as usize % n]
     }
 
     /// Returns an ID that is unique for this cache and that can be used to partition the cache
@@ -184,7 +425,7 @@ impl<T> Cache<T> {
 
     /// How many the cache currently contains
     pub fn count(&self) -> usize {
-        self.list.count()
+        self.shards.iter().map(|s| s.map.len()).sum()
     }
 
     /// The capacity of this cache
@@ -192,44 +433,83 @@ impl<T> Cache<T> {
         self.cap
     }
 
-    /// Insert a new element into the cache. The returned `CacheHandle` can be used for further
-    /// operations on that element.
+    pub fn policy(&self) -> EvictionPolicy {
+        self.policy
+    }
+
+    /// Returns the sum of the charges of all elements in the cache.
+    pub fn usage(&self) -> usize {
+        self.shards.iter().map(|s| s.usage).sum()
+    }
+
+    /// Change the capacity, evicting elements if the cache has become too full.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0);
+        let n = self.shards.len();
+        self.cap = capacity;
+        for shard in self.shards.iter_mut() {
+            shard.cap = capacity.div_ceil(n);
+            shard.make_room(0);
+        }
+    }
+
+    /// Insert a new element into the cache, charging 1 against the capacity.
     /// If the capacity has been reached, the least recently used element is removed from the
     /// cache.
     pub fn insert(&mut self, key: &CacheKey, elem: T) {
-        if self.list.count() >= self.cap {
-            if let Some(removed_key) = self.list.remove_last() {
-                assert!(self.map.remove(&removed_key).is_some());
-            } else {
-                panic!("could not remove_last(); bug!");
-            }
-        }
+        self.insert_with_charge(key, elem, 1)
+    }
+
+    /// Insert a new element into the cache, charging `charge` against the capacity. The least
+    /// recently used elements are removed from the cache until the new one fits.
+    pub fn insert_with_charge(&mut self, key: &CacheKey, elem: T, charge: usize) {
+        self.insert_with_priority(key, elem, charge, Priority::Low)
+    }
 
-        let lru_handle = self.list.insert(*key);
-        self.map.insert(*key, (elem, lru_handle));
+    /// Like `insert_with_charge()`, but with a priority for eviction.
+    pub fn insert_with_priority(
+        &mut self,
+        key: &CacheKey,
+        elem: T,
+        charge: usize,
+        priority: Priority,
+    ) {
+        self.shard(key).insert(key, elem, charge, priority)
     }
 
     /// Retrieve an element from the cache.
     /// If the element has been preempted from the cache in the meantime, this returns None.
     pub fn get<'a>(&'a mut self, key: &CacheKey) -> Option<&'a T> {
-        match self.map.get(key) {
-            None => None,
-            Some(&(ref elem, ref lru_handle)) => {
-                self.list.reinsert_front(*lru_handle);
-                Some(elem)
-            }
-        }
+        self.shard(key).get(key)
     }
 
     /// Remove an element from the cache (for invalidation).
     pub fn remove(&mut self, key: &CacheKey) -> Option<T> {
-        match self.map.remove(key) {
-            None => None,
-            Some((elem, lru_handle)) => {
-                self.list.remove(lru_handle);
-                Some(elem)
-            }
-        }
+        self.shard(key).remove(key)
+    }
+
+    /// Exempt an element from eviction until it is unpinned as often as it was pinned. Returns
+    /// false if the element is not in the cache.
+    pub fn pin(&mut self, key: &CacheKey) -> bool {
+        self.shard(key).pin(key)
+    }
+
+    /// Undo one `pin()` of an element.
+    pub fn unpin(&mut self, key: &CacheKey) {
+        self.shard(key).unpin(key)
+    }
+}
+
+impl Cache<Block> {
+    /// Returns the charges of the cached blocks that are pinned or also in use elsewhere, e.g. by
+    /// iterators. Their memory would not be freed by evicting them.
+    pub fn pinned_usage(&self) -> usize {
+        self.shards
+            .iter()
+            .flat_map(|s| s.map.values())
+            .filter(|e| e.pins > 0 || e.elem.is_pinned())
+            .map(|e| e.charge)
+            .sum()
     }
 }
 
@@ -295,6 +575,138 @@ mod tests {
         assert_eq!(cache.get(&h_899), Some(&899));
     }
 
+    #[test]
+    fn test_blockcache_cache_charge() {
+        let mut cache = Cache::new(100);
+        let (k1, k2, k3) = (make_key(1, 0, 0), make_key(2, 0, 0), make_key(3, 0, 0));
+
+        cache.insert_with_charge(&k1, 1, 40);
+        cache.insert_with_charge(&k2, 2, 40);
+        assert_eq!(80, cache.usage());
+        // Replacing an element releases its old charge.
+        cache.insert_with_charge(&k2, 22, 50);
+        assert_eq!(90, cache.usage());
+        assert_eq!(2, cache.count());
+
+        // k1 is the least recently used element.
+        cache.insert_with_charge(&k3, 3, 30);
+        assert_eq!(None, cache.get(&k1));
+        assert_eq!(Some(&22), cache.get(&k2));
+        assert_eq!(80, cache.usage());
+
+        cache.set_capacity(60);
+        assert_eq!(None, cache.get(&k3));
+        assert_eq!(50, cache.usage());
+        assert_eq!(Some(22), cache.remove(&k2));
+        assert_eq!(0, cache.usage());
+
+        // Elements larger than the capacity are cached on their own.
+        cache.insert_with_charge(&k1, 1, 10);
+        cache.insert_with_charge(&k3, 3, 200);
+        assert_eq!((1, 200), (cache.count(), cache.usage()));
+    }
+
+    #[test]
+    fn test_blockcache_cache_sharded() {
+        let mut cache = Cache::new_sharded(64, 2);
+        assert_eq!(4, cache.shards.len());
+        for i in 0..64 {
+            cache.insert(&make_key(i, 0, 0), i);
+        }
+        assert!(cache.count() <= 64);
+        assert_eq!(cache.count(), cache.usage());
+        for shard in cache.shards.iter() {
+            assert!(shard.usage <= 16);
+        }
+        assert_eq!(Some(&63), cache.get(&make_key(63, 0, 0)));
+    }
+
+    #[test]
+    fn test_blockcache_cache_priority_and_pins() {
+        let mut cache = Cache::new(3);
+        let (k1, k2, k3, k4) = (
+            make_key(1, 0, 0),
+            make_key(2, 0, 0),
+            make_key(3, 0, 0),
+            make_key(4, 0, 0),
+        );
+
+        cache.insert_with_priority(&k1, 1, 1, Priority::High);
+        cache.insert(&k2, 2);
+        cache.insert(&k3, 3);
+        cache.insert(&k4, 4);
+        // The low-priority k2 is evicted, although k1 is older.
+        assert_eq!(None, cache.get(&k2));
+        assert_eq!(Some(&1), cache.get(&k1));
+
+        assert!(cache.pin(&k3));
+        assert!(!cache.pin(&k2));
+        cache.set_capacity(1);
+        assert_eq!((1, 1), (cache.count(), cache.usage()));
+        assert_eq!(Some(&3), cache.get(&k3));
+
+        cache.unpin(&k3);
+        cache.insert(&k4, 4);
+        assert_eq!(None, cache.get(&k3));
+        assert_eq!(Some(&4), cache.get(&k4));
+    }
+
+    #[test]
+    fn test_blockcache_cache_segmented_lru() {
+        for policy in [EvictionPolicy::Lru, EvictionPolicy::SegmentedLru] {
+            let mut cache = Cache::new_with_policy(10, 0, policy);
+            // Three hot elements, then a scan over many others.
+            for i in 0..3 {
+                cache.insert(&make_key(i, 0, 0), i);
+                assert_eq!(Some(&i), cache.get(&make_key(i, 0, 0)));
+            }
+            for i in 10..30 {
+                cache.insert(&make_key(i, 0, 0), i);
+            }
+            assert_eq!(10, cache.usage());
+            let hot = (0..3).filter(|&i| cache.get(&make_key(i, 0, 0)).is_some());
+            let expected = if policy == EvictionPolicy::Lru { 0 } else { 3 };
+            assert_eq!(expected, hot.count());
+        }
+
+        // The protected segment takes up at most 80% of the capacity; the least recently used
+        // elements are demoted once it is full.
+        let mut cache = Cache::new_with_policy(10, 0, EvictionPolicy::SegmentedLru);
+        for i in 0..10 {
+            cache.insert(&make_key(i, 0, 0), i);
+            cache.get(&make_key(i, 0, 0));
+        }
+        assert_eq!(8, cache.shards[0].protected_usage);
+        cache.insert(&make_key(10, 0, 0), 10);
+        cache.insert(&make_key(11, 0, 0), 11);
+        // 0 and 1 were demoted, and then evicted first.
+        assert_eq!(None, cache.get(&make_key(0, 0, 0)));
+        assert_eq!(None, cache.get(&make_key(1, 0, 0)));
+        assert_eq!(Some(&2), cache.get(&make_key(2, 0, 0)));
+        assert_eq!(Some(&9), cache.get(&make_key(9, 0, 0)));
+        assert_eq!(Some(&11), cache.get(&make_key(11, 0, 0)));
+        assert_eq!(cache.count(), cache.usage());
+        assert!(cache.remove(&make_key(2, 0, 0)).is_some());
+        assert!(cache.shards[0].protected_usage <= 8);
+    }
+
+    #[test]
+    fn test_blockcache_cache_pinned_usage() {
+        let opt = crate::options::for_test();
+        let mut cache = Cache::new(1000);
+        let b1 = Block::new(opt.clone(), vec![0; 100]);
+        let b2 = Block::new(opt, vec![0; 100]);
+        cache.insert_with_charge(&make_key(1, 0, 0), b1, 100);
+        cache.insert_with_charge(&make_key(2, 0, 0), b2, 100);
+        assert_eq!(200, cache.usage());
+        assert_eq!(0, cache.pinned_usage());
+
+        let iter = cache.get(&make_key(2, 0, 0)).unwrap().iter();
+        assert_eq!(100, cache.pinned_usage());
+        drop(iter);
+        assert_eq!(0, cache.pinned_usage());
+    }
+
     #[test]
     fn test_blockcache_lru_remove() {
         let mut lru = LRUList::<usize>::new();
@@ -315,6 +727,22 @@ mod tests {
         assert_eq!(lru.count(), 3);
     }
 
+    #[test]
+    fn test_blockcache_lru_remove_last_node() {
+        let mut lru = LRUList::<usize>::new();
+
+        let h_56 = lru.insert(56);
+        let h_22 = lru.insert(22);
+        lru.insert(223);
+
+        // Removing the last node makes its predecessor the last one.
+        assert_eq!(56, lru.remove(h_56));
+        assert_eq!(Some(h_22), lru.head.prev);
+        assert_eq!(Some(22), lru.remove_last());
+        assert_eq!(Some(223), lru.remove_last());
+        assert_eq!(lru.count(), 0);
+    }
+
     #[test]
     fn test_blockcache_lru_1() {
         let mut lru = LRUList::<usize>::new();
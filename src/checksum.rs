@@ -0,0 +1,86 @@
+//! checksum computes the checksums of table blocks and log records. CRC32C is computed using the
+//! SSE4.2 instruction where available.
+
+use crate::log::mask_crc;
+use crate::options::ChecksumType;
+
+use crc::crc32;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Returns the CRC32C of `data`.
+pub fn crc32c(data: &[u8]) -> u32 {
+    crc32c_extend(0, data)
+}
+
+/// Returns the CRC32C of the concatenation of some data, whose CRC32C is `crc`, and `data`.
+pub fn crc32c_extend(crc: u32, data: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.2") {
+            return unsafe { crc32c_sse42(crc, data) };
+        }
+    }
+    crc32::update(crc, &crc32::CASTAGNOLI_TABLE, data)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn crc32c_sse42(crc: u32, data: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut words = data.chunks_exact(8);
+    let mut crc = u64::from(!crc);
+    for word in &mut words {
+        let mut w = [0; 8];
+        w.copy_from_slice(word);
+        crc = _mm_crc32_u64(crc, u64::from_le_bytes(w));
+    }
+    let mut crc = crc as u32;
+    for &b in words.remainder() {
+        crc = _mm_crc32_u8(crc, b);
+    }
+    !crc
+}
+
+/// Returns the checksum of a table block, stored in its trailer: It covers the (possibly
+/// compressed) `data` and the `compression` type byte.
+pub fn block_checksum(checksum: ChecksumType, data: &[u8], compression: u8) -> u32 {
+    match checksum {
+        ChecksumType::Crc32c => mask_crc(crc32c_extend(crc32c(data), &[compression])),
+        ChecksumType::Xxh3 => {
+            let mut hasher = Xxh3::new();
+            hasher.update(data);
+            hasher.update(&[compression]);
+            hasher.digest() as u32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_crc32c() {
+        assert_eq!(0x8a9136aa, crc32c(&[0; 32]));
+        assert_eq!(0x62a8ab43, crc32c(&[0xff; 32]));
+
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7 + i / 3) as u8).collect();
+        let want = crc32::checksum_castagnoli(&data);
+        assert_eq!(want, crc32c(&data));
+        assert_eq!(want, crc32c_extend(crc32c(&data[..333]), &data[333..]));
+        assert_eq!(
+            want,
+            crc32::update(crc32c(&data[..9]), &crc32::CASTAGNOLI_TABLE, &data[9..])
+        );
+    }
+
+    #[test]
+    fn test_checksum_block() {
+        let crc = block_checksum(ChecksumType::Crc32c, b"abc", 1);
+        assert_eq!(mask_crc(crc32::checksum_castagnoli(b"abc\x01")), crc);
+        let xxh3 = block_checksum(ChecksumType::Xxh3, b"abc", 1);
+        assert_eq!(xxhash_rust::xxh3::xxh3_64(b"abc\x01") as u32, xxh3);
+        assert_ne!(xxh3, block_checksum(ChecksumType::Xxh3, b"abc", 0));
+    }
+}
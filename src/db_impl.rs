@@ -7,17 +7,21 @@ use crate::db_iter::DBIterator;
 
 use crate::cmp::{Cmp, InternalKeyCmp};
 use crate::env::{Env, FileLock};
-use crate::error::{err, Result, StatusCode};
+use crate::error::{err, Result, Status, StatusCode};
+use crate::event_listener::EventListener;
 use crate::filter::{BoxedFilterPolicy, InternalFilterPolicy};
-use crate::infolog::Logger;
+use crate::infolog::{self, FileLogger, InfoLogger};
 use crate::key_types::{parse_internal_key, InternalKey, LookupKey, ValueType};
-use crate::log::{LogReader, LogWriter};
+use crate::log::{CorruptionReporter, LogReader, LogWriter};
 use crate::memtable::MemTable;
 use crate::merging_iter::MergingIter;
-use crate::options::Options;
+use crate::options::{CompactionStyle, Options, WalRecoveryMode, WriteOptions};
+use crate::rate_limiter::{rate_limited, IoSource};
 use crate::snapshot::{Snapshot, SnapshotList};
+use crate::statistics::{self, Histogram, Ticker};
 use crate::table_builder::TableBuilder;
 use crate::table_cache::{table_file_name, TableCache};
+use crate::table_reader::Table;
 use crate::types::{
     parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, SequenceNumber, Shared,
     MAX_SEQUENCE_NUMBER, NUM_LEVELS,
@@ -27,9 +31,12 @@ use crate::version_edit::VersionEdit;
 use crate::version_set::{
     manifest_file_name, read_current_file, set_current_file, Compaction, VersionSet,
 };
+use crate::wal_iter::WalIterator;
 use crate::write_batch::WriteBatch;
 
+use std::cell::Cell;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::io::{self, BufWriter, Write};
 use std::mem;
 use std::ops::Drop;
@@ -43,6 +50,8 @@ pub struct DB {
     name: PathBuf,
     path: PathBuf,
     lock: Option<FileLock>,
+    read_only: bool,
+    secondary: bool,
 
     internal_cmp: Rc<Box<dyn Cmp>>,
     fpol: InternalFilterPolicy<BoxedFilterPolicy>,
@@ -56,6 +65,15 @@ pub struct DB {
     cache: Shared<TableCache>,
     vset: Shared<VersionSet>,
     snaps: SnapshotList,
+    // In read-only mode: the log files replayed into the memtable, and the number of records read
+    // from each.
+    replayed_logs: BTreeMap<FileNum, usize>,
+    // Obsolete log files retained for get_updates_since(), mapped to the time (in micros) at which
+    // they were found to be obsolete.
+    retained_logs: BTreeMap<FileNum, u64>,
+    // With paranoid_checks, the first corruption found by a read or compaction. Once set, writes
+    // and compactions fail.
+    bg_error: Shared<Option<Status>>,
 
     cstats: [CompactionStats; NUM_LEVELS],
 }
@@ -69,7 +87,7 @@ impl DB {
     fn new<P: AsRef<Path>>(name: P, mut opt: Options) -> DB {
         let name = name.as_ref();
         if opt.log.is_none() {
-            let log = open_info_log(opt.env.as_ref().as_ref(), name);
+            let log = open_info_log(&opt, name);
             opt.log = Some(share(log));
         }
         let path = name.canonicalize().unwrap_or(name.to_owned());
@@ -81,6 +99,8 @@ impl DB {
             name: name.to_owned(),
             path,
             lock: None,
+            read_only: false,
+            secondary: false,
             internal_cmp: Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone()))),
             fpol: InternalFilterPolicy::new(opt.filter_policy.clone()),
 
@@ -94,6 +114,9 @@ impl DB {
             cache,
             vset: share(vset),
             snaps: SnapshotList::new(),
+            replayed_logs: BTreeMap::new(),
+            retained_logs: BTreeMap::new(),
+            bg_error: share(None),
 
             cstats: Default::default(),
         }
@@ -109,6 +132,7 @@ impl DB {
     /// Whether a new database is created and what happens if a database exists at the given path
     /// depends on the options set (`create_if_missing`, `error_if_exists`).
     pub fn open<P: AsRef<Path>>(name: P, opt: Options) -> Result<DB> {
+        opt.validate()?;
         let name = name.as_ref();
         let mut db = DB::new(name, opt);
         let mut ve = VersionEdit::new();
@@ -136,6 +160,132 @@ impl DB {
         Ok(db)
     }
 
+    /// Opens an existing database without modifying it in any way. Log files are replayed into
+    /// the memtable only; no tables are written and no compactions are run. As the lock is not
+    /// taken, several read-only instances can share the same database, provided it isn't
+    /// modified at the same time.
+    ///
+    /// All write operations on the returned DB fail with `StatusCode::NotSupported`.
+    pub fn open_read_only<P: AsRef<Path>>(name: P, mut opt: Options) -> Result<DB> {
+        opt.validate()?;
+        // Opening the default info log would rotate it.
+        if opt.log.is_none() {
+            opt.log = Some(share(Box::new(infolog::sink(opt.env.clone()))));
+        }
+        let mut db = DB::new(name, opt);
+        db.read_only = true;
+        db.recover(&mut VersionEdit::new())?;
+        Ok(db)
+    }
+
+    /// Opens a database as a secondary instance, i.e. a read-only instance following a primary
+    /// instance that is writing to the same database concurrently (in another process). Call
+    /// `try_catch_up_with_primary()` to make the primary's recent changes visible.
+    ///
+    /// Entries are only visible once the primary has flushed them to its log. Tables that the
+    /// primary deletes after compactions can't be read anymore (unless they're still open), so
+    /// the secondary should catch up regularly.
+    pub fn open_as_secondary<P: AsRef<Path>>(name: P, opt: Options) -> Result<DB> {
+        let mut db = DB::open_read_only(name, opt)?;
+        db.secondary = true;
+        Ok(db)
+    }
+
+    /// try_catch_up_with_primary reads the changes that the primary instance has written to its
+    /// manifest and log files since the secondary was opened or last caught up, and updates the
+    /// current version and memtable accordingly.
+    pub fn try_catch_up_with_primary(&mut self) -> Result<()> {
+        if !self.secondary {
+            return err(
+                StatusCode::NotSupported,
+                "database is not opened as secondary instance",
+            );
+        }
+        let mut last_seq = self.vset.borrow().last_seq;
+        self.vset.borrow_mut().catch_up()?;
+
+        let (log_num, prev_log_num) = {
+            let vset = self.vset.borrow();
+            (vset.log_num, vset.prev_log_num)
+        };
+        let mut log_files = vec![];
+        for name in self.opt.env.children(&self.path)? {
+            if let Ok((num, FileType::Log)) = parse_file_name(&name) {
+                if num >= log_num || num == prev_log_num {
+                    log_files.push(num);
+                }
+            }
+        }
+        log_files.sort();
+
+        // If the primary has written log files to tables, start over with a new memtable.
+        if self
+            .replayed_logs
+            .keys()
+            .any(|num| log_files.binary_search(num).is_err())
+        {
+            self.mem = MemTable::new(self.opt.cmp.clone());
+            self.replayed_logs.clear();
+        }
+
+        let mut ve = VersionEdit::new();
+        for num in log_files {
+            let (_, max_seq, _) = self.recover_log_file(num, false, &mut ve)?;
+            if max_seq > last_seq {
+                last_seq = max_seq;
+            }
+        }
+
+        let mut vset = self.vset.borrow_mut();
+        if vset.last_seq < last_seq {
+            vset.last_seq = last_seq;
+        }
+        Ok(())
+    }
+
+    /// check_bg_error returns the error that put the database into an error state, if any.
+    fn check_bg_error(&self) -> Result<()> {
+        match *self.bg_error.borrow() {
+            Some(ref e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// record_bg_error puts the database into an error state, unless it already is in one.
+    fn record_bg_error(&self, e: &Status) {
+        if self.bg_error.borrow().is_none() {
+            log!(self.opt.log, Error, "Entering error state: {}", e);
+            *self.bg_error.borrow_mut() = Some(e.clone());
+            self.notify(|l| l.on_background_error(e));
+        }
+    }
+
+    /// background_error reports a failed flush or compaction to the event listeners. With
+    /// `paranoid_checks`, the database also enters the error state.
+    fn background_error(&self, e: &Status) {
+        if self.opt.paranoid_checks {
+            self.record_bg_error(e);
+        } else {
+            self.notify(|l| l.on_background_error(e));
+        }
+    }
+
+    /// notify calls `f` for every registered event listener.
+    fn notify<F: Fn(&dyn EventListener)>(&self, f: F) {
+        for l in &self.opt.listeners {
+            f(l.as_ref().as_ref());
+        }
+    }
+
+    /// check_writable returns an error if the database was opened read-only.
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            err(StatusCode::NotSupported, "database is opened read-only")
+        } else {
+            Ok(())
+        }
+    }
+
     /// initialize_db initializes a new database.
     fn initialize_db(&mut self) -> Result<()> {
         let mut ve = VersionEdit::new();
@@ -157,15 +307,18 @@ impl DB {
     /// recover recovers from the existing state on disk. If the wrapped result is `true`, then
     /// log_and_apply() should be called after recovery has finished.
     fn recover(&mut self, ve: &mut VersionEdit) -> Result<bool> {
-        if self.opt.error_if_exists && self.opt.env.exists(&self.path.as_ref()).unwrap_or(false) {
-            return err(StatusCode::AlreadyExists, "database already exists");
-        }
+        if !self.read_only {
+            if self.opt.error_if_exists && self.opt.env.exists(self.path.as_ref()).unwrap_or(false)
+            {
+                return err(StatusCode::AlreadyExists, "database already exists");
+            }
 
-        let _ = self.opt.env.mkdir(Path::new(&self.path));
-        self.acquire_lock()?;
+            let _ = self.opt.env.mkdir(Path::new(&self.path));
+            self.acquire_lock()?;
+        }
 
         if let Err(e) = read_current_file(&self.opt.env, &self.path) {
-            if e.code == StatusCode::NotFound && self.opt.create_if_missing {
+            if e.code == StatusCode::NotFound && self.opt.create_if_missing && !self.read_only {
                 self.initialize_db()?;
             } else {
                 return err(
@@ -177,7 +330,12 @@ impl DB {
 
         // If save_manifest is true, we should log_and_apply() later in order to write the new
         // manifest.
-        let mut save_manifest = self.vset.borrow_mut().recover()?;
+        let mut save_manifest = if self.read_only {
+            self.vset.borrow_mut().recover_read_only()?;
+            false
+        } else {
+            self.vset.borrow_mut().recover()?
+        };
 
         // Recover from all log files not in the descriptor.
         let mut max_seq = 0;
@@ -204,14 +362,21 @@ impl DB {
         }
 
         log_files.sort();
+        let mut stopped = false;
         for i in 0..log_files.len() {
-            let (save_manifest_, max_seq_) =
-                self.recover_log_file(log_files[i], i == log_files.len() - 1, ve)?;
-            if save_manifest_ {
-                save_manifest = true;
-            }
-            if max_seq_ > max_seq {
-                max_seq = max_seq_;
+            if stopped {
+                // Point-in-time recovery doesn't replay anything after a corruption.
+                log!(self.opt.log, "Skipping log file {:06}", log_files[i]);
+            } else {
+                let (save_manifest_, max_seq_, stopped_) =
+                    self.recover_log_file(log_files[i], i == log_files.len() - 1, ve)?;
+                if save_manifest_ {
+                    save_manifest = true;
+                }
+                if max_seq_ > max_seq {
+                    max_seq = max_seq_;
+                }
+                stopped = stopped_;
             }
             self.vset.borrow_mut().mark_file_number_used(log_files[i]);
         }
@@ -226,12 +391,16 @@ impl DB {
     /// recover_log_file reads a single log file into a memtable, writing new L0 tables if
     /// necessary. If is_last is true, it checks whether the log file can be reused, and sets up
     /// the database's logging handles appropriately if that's the case.
+    ///
+    /// Corrupted records are treated according to `opt.wal_recovery_mode`. The third returned
+    /// value is true if recovery stopped at a corruption, in which case later log files must not
+    /// be replayed.
     fn recover_log_file(
         &mut self,
         log_num: FileNum,
         is_last: bool,
         ve: &mut VersionEdit,
-    ) -> Result<(bool, SequenceNumber)> {
+    ) -> Result<(bool, SequenceNumber, bool)> {
         let filename = log_file_name(&self.path, log_num);
         let logfile = self.opt.env.open_sequential_file(Path::new(&filename))?;
         // Use the user-supplied comparator; it will be wrapped inside a MemtableKeyCmp.
@@ -241,24 +410,90 @@ impl DB {
             logfile, // checksum=
             true,
         );
+        let corruptions = Rc::new(Cell::new(0));
+        let mut reporter = LogCorruptionReporter {
+            log: self.opt.log.clone(),
+            user: self.opt.corruption_reporter.clone(),
+            log_num,
+            corruptions: corruptions.clone(),
+        };
+        logreader.set_reporter(Box::new(reporter.clone()));
+        if self.read_only {
+            // The primary may be writing the last record right now; it's read when catching up
+            // the next time.
+            logreader.ignore_truncated_tail();
+        }
+        let mode = if self.opt.paranoid_checks {
+            WalRecoveryMode::AbsoluteConsistency
+        } else {
+            self.opt.wal_recovery_mode
+        };
         log!(self.opt.log, "Recovering log file {:?}", filename);
         let mut scratch = vec![];
-        let mut mem = MemTable::new(cmp.clone());
+        // A read-only database accumulates all log files in its memtable.
+        let mut mem = if self.read_only {
+            mem::replace(&mut self.mem, MemTable::new(cmp.clone()))
+        } else {
+            MemTable::new(cmp.clone())
+        };
         let mut batch = WriteBatch::new();
 
         let mut compactions = 0;
         let mut max_seq = 0;
         let mut save_manifest = false;
-
-        while let Ok(len) = logreader.read(&mut scratch) {
+        // Skip records that a read-only instance has already replayed.
+        let skip = self.replayed_logs.get(&log_num).cloned().unwrap_or(0);
+        let mut records = 0;
+        let mut stopped = false;
+
+        loop {
+            let len = match logreader.read(&mut scratch) {
+                Ok(len) => len,
+                Err(e) => {
+                    if mode == WalRecoveryMode::AbsoluteConsistency {
+                        return Err(e);
+                    }
+                    log!(
+                        self.opt.log,
+                        Warn,
+                        "Error reading log file {:06}: {}",
+                        log_num,
+                        e
+                    );
+                    stopped = mode != WalRecoveryMode::DropCorruptedLogTails;
+                    break;
+                }
+            };
+            if corruptions.get() > 0 {
+                match mode {
+                    WalRecoveryMode::DropCorruptedLogTails => break,
+                    WalRecoveryMode::PointInTimeRecovery => {
+                        stopped = true;
+                        break;
+                    }
+                    WalRecoveryMode::AbsoluteConsistency
+                    | WalRecoveryMode::TolerateCorruptedTailRecords
+                        if len > 0 =>
+                    {
+                        return err(
+                            StatusCode::Corruption,
+                            &format!("corrupted records in log file {:06}", log_num),
+                        );
+                    }
+                    _ => (),
+                }
+            }
             if len == 0 {
                 break;
             }
+            records += 1;
+            if records <= skip {
+                continue;
+            }
             if len < 12 {
-                log!(
-                    self.opt.log,
-                    "corruption in log file {:06}: record shorter than 12B",
-                    log_num
+                reporter.corruption(
+                    len,
+                    &Status::new(StatusCode::Corruption, "record shorter than 12B"),
                 );
                 continue;
             }
@@ -270,7 +505,7 @@ impl DB {
             if last_seq > max_seq {
                 max_seq = last_seq
             }
-            if mem.approx_mem_usage() > self.opt.write_buffer_size {
+            if mem.approx_mem_usage() > self.opt.write_buffer_size && !self.read_only {
                 compactions += 1;
                 self.write_l0_table(&mem, ve, None)?;
                 save_manifest = true;
@@ -279,8 +514,18 @@ impl DB {
             batch.clear();
         }
 
+        if corruptions.get() > 0 && mode == WalRecoveryMode::AbsoluteConsistency {
+            return err(
+                StatusCode::Corruption,
+                &format!("corrupted tail in log file {:06}", log_num),
+            );
+        }
+
         // Check if we can reuse the last log file.
-        if self.opt.reuse_logs && is_last && compactions == 0 {
+        if self.read_only {
+            self.mem = mem;
+            self.replayed_logs.insert(log_num, records);
+        } else if self.opt.reuse_logs && is_last && compactions == 0 && corruptions.get() == 0 {
             assert!(self.log.is_none());
             log!(self.opt.log, "reusing log file {:?}", filename);
             let oldsize = self.opt.env.size_of(Path::new(&filename))?;
@@ -295,13 +540,17 @@ impl DB {
             self.write_l0_table(&mem, ve, None)?;
         }
 
-        Ok((save_manifest, max_seq))
+        Ok((save_manifest, max_seq, stopped))
     }
 
     /// delete_obsolete_files removes files that are no longer needed from the file system.
     fn delete_obsolete_files(&mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
         let files = self.vset.borrow().live_files();
         let filenames = self.opt.env.children(Path::new(&self.path))?;
+        let mut obsolete_logs = vec![];
         for name in filenames {
             if let Ok((num, typ)) = parse_file_name(&name) {
                 match typ {
@@ -309,6 +558,10 @@ impl DB {
                         if num >= self.vset.borrow().log_num {
                             continue;
                         }
+                        if self.opt.wal_ttl_seconds > 0 || self.opt.wal_size_limit > 0 {
+                            obsolete_logs.push(num);
+                            continue;
+                        }
                     }
                     FileType::Descriptor => {
                         if num >= self.vset.borrow().manifest_num {
@@ -336,10 +589,75 @@ impl DB {
                 }
                 log!(self.opt.log, "Deleting file type={:?} num={}", typ, num);
                 if let Err(e) = self.opt.env.delete(&self.path.join(&name)) {
-                    log!(self.opt.log, "Deleting file num={} failed: {}", num, e);
+                    log!(
+                        self.opt.log,
+                        Warn,
+                        "Deleting file num={} failed: {}",
+                        num,
+                        e
+                    );
+                } else if typ == FileType::Table {
+                    self.notify(|l| l.on_table_file_deleted(num));
                 }
             }
         }
+        self.purge_retained_logs(obsolete_logs)
+    }
+
+    /// purge_retained_logs deletes those of the given obsolete log files which exceed the
+    /// retention budget set by `wal_ttl_seconds` and `wal_size_limit`. The oldest files are
+    /// deleted first.
+    ///
+    /// The age of a log file not seen before, e.g. one retained before the database was opened,
+    /// is counted from its last modification.
+    fn purge_retained_logs(&mut self, obsolete: Vec<FileNum>) -> Result<()> {
+        let now = self.opt.env.micros();
+        let mut retained = BTreeMap::new();
+        for num in obsolete {
+            let since = match self.retained_logs.get(&num) {
+                Some(since) => *since,
+                None => self
+                    .opt
+                    .env
+                    .modified_micros(&log_file_name(&self.path, num))
+                    .unwrap_or(now),
+            };
+            retained.insert(num, since);
+        }
+        self.retained_logs = retained;
+
+        let mut sizes = vec![];
+        let mut total = 0;
+        for num in self.retained_logs.keys() {
+            let size = self
+                .opt
+                .env
+                .size_of(&log_file_name(&self.path, *num))
+                .unwrap_or(0);
+            sizes.push((*num, size));
+            total += size;
+        }
+
+        let ttl = self.opt.wal_ttl_seconds * 1_000_000;
+        for (num, size) in sizes {
+            let expired = ttl > 0 && now.saturating_sub(self.retained_logs[&num]) > ttl;
+            let too_large = self.opt.wal_size_limit > 0 && total > self.opt.wal_size_limit;
+            if !expired && !too_large {
+                continue;
+            }
+            log!(self.opt.log, "Deleting retained log file num={}", num);
+            if let Err(e) = self.opt.env.delete(&log_file_name(&self.path, num)) {
+                log!(
+                    self.opt.log,
+                    Warn,
+                    "Deleting file num={} failed: {}",
+                    num,
+                    e
+                );
+            }
+            self.retained_logs.remove(&num);
+            total -= size;
+        }
         Ok(())
     }
 
@@ -368,9 +686,15 @@ impl DB {
         }
     }
 
-    /// Flush data to disk and release lock.
+    /// Flush data to disk and release lock. With `flush_memtable_on_close`, the memtable is written
+    /// to a table as well.
     pub fn close(&mut self) -> Result<()> {
-        self.flush()?;
+        if !self.read_only {
+            if self.opt.flush_memtable_on_close {
+                self.flush_memtable(true)?;
+            }
+            self.flush()?;
+        }
         self.release_lock()?;
         Ok(())
     }
@@ -398,54 +722,296 @@ impl DB {
     /// Writes an entire WriteBatch. `sync` determines whether the write should be flushed to
     /// disk.
     pub fn write(&mut self, batch: WriteBatch, sync: bool) -> Result<()> {
+        self.write_opt(
+            batch,
+            &WriteOptions {
+                sync,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Writes an entire WriteBatch, as determined by `wopt`.
+    pub fn write_opt(&mut self, batch: WriteBatch, wopt: &WriteOptions) -> Result<()> {
+        self.check_writable()?;
+        self.check_bg_error()?;
         assert!(self.log.is_some());
 
+        let start = statistics::start_timer(&self.opt);
         self.make_room_for_write(false)?;
 
+        statistics::record_tick(&self.opt, Ticker::BytesWritten, batch.byte_size() as u64);
         let entries = batch.count() as u64;
         let log = self.log.as_mut().unwrap();
         let next = self.vset.borrow().last_seq + 1;
 
         batch.insert_into_memtable(next, &mut self.mem);
-        log.add_record(&batch.encode(next))?;
-        if sync {
-            log.flush()?;
+        if !wopt.disable_wal {
+            log.add_record(&batch.encode(next))?;
+            if wopt.sync {
+                log.flush()?;
+            }
         }
         self.vset.borrow_mut().last_seq += entries;
+        statistics::record_time(&self.opt, Histogram::Write, start);
         Ok(())
     }
 
     /// flush makes sure that all pending changes (e.g. from put()) are stored on disk.
     pub fn flush(&mut self) -> Result<()> {
+        self.check_writable()?;
         assert!(self.log.is_some());
         self.log.as_mut().unwrap().flush()
     }
 }
 
+impl DB {
+    // CHANGE DATA CAPTURE //
+
+    /// get_updates_since returns an iterator over all write batches with entries at or after the
+    /// sequence number `seq`, read from the current and retained log files. Set
+    /// `wal_ttl_seconds` or `wal_size_limit` in the options to keep log files around after their
+    /// contents have been written to tables.
+    ///
+    /// Entries added through `ingest_external_file()` are not written to the log and are
+    /// therefore not returned.
+    pub fn get_updates_since(&mut self, seq: SequenceNumber) -> Result<WalIterator> {
+        if let Some(ref mut log) = self.log {
+            log.flush()?;
+        }
+        let mut logs = vec![];
+        for name in self.opt.env.children(&self.path)? {
+            if let Ok((num, FileType::Log)) = parse_file_name(&name) {
+                logs.push(num);
+            }
+        }
+        logs.sort();
+        Ok(WalIterator::new(
+            self.opt.env.clone(),
+            self.path.clone(),
+            logs,
+            seq,
+        ))
+    }
+}
+
+impl DB {
+    // INGESTION //
+
+    /// ingest_external_file adds table files created by an `SstFileWriter` to the database. The
+    /// files' key ranges must not overlap each other. All entries are assigned a single new
+    /// sequence number, i.e. they shadow all previous entries for the same keys.
+    ///
+    /// The files are not rewritten: They are hard-linked into the database directory if the `Env`
+    /// supports it, and copied otherwise. The assigned sequence number is only recorded in the
+    /// manifest, and applied to the entries, which are stored with sequence number 0, when they
+    /// are read (see `Table::with_global_seq()`). The original files may be deleted afterwards,
+    /// but must not be modified. Each new table is placed at the deepest level which has no
+    /// overlap with it in any level above.
+    pub fn ingest_external_file<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<()> {
+        self.check_writable()?;
+        self.check_bg_error()?;
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let mut files = Vec::with_capacity(paths.len());
+        for p in paths {
+            let (size, smallest, largest) = self.open_external_file(p.as_ref())?;
+            files.push((p.as_ref(), size, smallest, largest));
+        }
+        let ukey = |ikey: &[u8]| parse_internal_key(ikey).2.to_vec();
+        files.sort_by(|a, b| self.opt.cmp.cmp(&ukey(&a.2), &ukey(&b.2)));
+        for i in 1..files.len() {
+            if self.opt.cmp.cmp(&ukey(&files[i - 1].3), &ukey(&files[i].2)) != Ordering::Less {
+                return err(
+                    StatusCode::InvalidArgument,
+                    "key ranges of ingested files overlap",
+                );
+            }
+        }
+
+        // Entries in the memtable would be found before the ingested ones, even though they are
+        // older.
+        if files.iter().any(|(_, _, smallest, largest)| {
+            self.memtable_overlaps(&ukey(smallest), &ukey(largest))
+        }) {
+            self.make_room_for_write(true)?;
+        }
+
+        let seq = self.vset.borrow().last_seq + 1;
+        let mut ve = VersionEdit::new();
+        let mut written = vec![];
+        let base = self.current();
+
+        for (path, size, smallest, largest) in &files {
+            let num = self.vset.borrow_mut().new_file_number();
+            written.push(num);
+            let r = self.place_external_file(path, num).and_then(|copied| {
+                self.cache.borrow_mut().get_table(num)?;
+                Ok(copied)
+            });
+            let copied = match r {
+                Ok(copied) => copied,
+                Err(e) => {
+                    self.delete_ingested_tables(&written);
+                    return Err(e);
+                }
+            };
+
+            let (smallest, largest) = (with_seq(smallest, seq), with_seq(largest, seq));
+            let level = if self.opt.compaction_style == CompactionStyle::Universal {
+                0
+            } else {
+                base.borrow()
+                    .pick_ingestion_level(&ukey(&smallest), &ukey(&largest))
+            };
+            log!(
+                self.opt.log,
+                "Ingesting external file as {:06} at L{} with {} bytes ({} copied)",
+                num,
+                level,
+                size,
+                copied
+            );
+            self.notify(|l| l.on_table_file_created(num, *size));
+            self.add_stats(
+                level,
+                CompactionStats {
+                    written: copied,
+                    ..Default::default()
+                },
+            );
+            ve.add_file(
+                level,
+                FileMetaData {
+                    num,
+                    size: *size,
+                    smallest,
+                    largest,
+                    ..Default::default()
+                },
+            );
+        }
+
+        self.vset.borrow_mut().last_seq = seq;
+        let r = self.vset.borrow_mut().log_and_apply(ve);
+        if r.is_err() {
+            self.vset.borrow_mut().last_seq = seq - 1;
+            self.delete_ingested_tables(&written);
+        }
+        r
+    }
+
+    /// Remove the tables written by a failed ingestion.
+    fn delete_ingested_tables(&mut self, nums: &[FileNum]) {
+        for &num in nums {
+            let _ = self.cache.borrow_mut().evict(num);
+            let _ = self.opt.env.delete(&table_file_name(&self.path, num));
+        }
+    }
+
+    /// place_external_file hard-links the file at `path` to the table file `num`, or copies it if
+    /// that's not possible. Returns the number of bytes copied.
+    fn place_external_file(&self, path: &Path, num: FileNum) -> Result<usize> {
+        let name = table_file_name(&self.path, num);
+        if self.opt.env.link(path, &name).is_ok() {
+            return Ok(0);
+        }
+        let mut src = self.opt.env.open_sequential_file(path)?;
+        let mut dst = self.opt.env.open_writable_file(&name)?;
+        let copied = io::copy(&mut src, &mut dst)?;
+        dst.flush()?;
+        Ok(copied as usize)
+    }
+
+    /// open_external_file checks a table written by an SstFileWriter, and returns its size along
+    /// with its smallest and largest keys.
+    fn open_external_file(&self, path: &Path) -> Result<(usize, Vec<u8>, Vec<u8>)> {
+        let size = self.opt.env.size_of(path)?;
+        if size == 0 {
+            return err(StatusCode::InvalidArgument, "external file is empty");
+        }
+        let file = Rc::new(self.opt.env.open_random_access_file(path)?);
+        let table = Table::new(self.opt.clone(), file, size)?;
+
+        let mut iter = table.iter();
+        let (mut key, mut val) = (vec![], vec![]);
+        let (mut smallest, mut largest) = (None, vec![]);
+        while iter.advance() {
+            iter.current(&mut key, &mut val);
+            if parse_internal_key(&key).1 != 0 {
+                return err(
+                    StatusCode::InvalidArgument,
+                    "external file contains entries with a sequence number",
+                );
+            }
+            if smallest.is_none() {
+                smallest = Some(key.clone());
+            }
+            largest.clear();
+            largest.extend_from_slice(&key);
+        }
+        match smallest {
+            Some(smallest) => Ok((size, smallest, largest)),
+            None => err(StatusCode::InvalidArgument, "external file is empty"),
+        }
+    }
+
+    /// memtable_overlaps returns true if the memtable or immutable memtable contain keys in the
+    /// user key range [smallest; largest].
+    fn memtable_overlaps(&self, smallest: &[u8], largest: &[u8]) -> bool {
+        let start = LookupKey::new(smallest, MAX_SEQUENCE_NUMBER);
+        let (mut key, mut val) = (vec![], vec![]);
+        for mt in std::iter::once(&self.mem).chain(self.imm.iter()) {
+            let mut iter = mt.iter();
+            iter.seek(start.internal_key());
+            if iter.current(&mut key, &mut val)
+                && self.opt.cmp.cmp(parse_internal_key(&key).2, largest) != Ordering::Greater
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
 impl DB {
     // READ //
 
     fn get_internal(&mut self, seq: SequenceNumber, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let start = statistics::start_timer(&self.opt);
+        let r = self.lookup(seq, key);
+        if let Ok(Some(ref v)) = r {
+            statistics::record_tick(&self.opt, Ticker::BytesRead, v.len() as u64);
+        }
+        statistics::record_time(&self.opt, Histogram::Get, start);
+        r
+    }
+
+    fn lookup(&mut self, seq: SequenceNumber, key: &[u8]) -> Result<Option<Vec<u8>>> {
         // Using this lookup key will skip all entries with higher sequence numbers, because they
         // will compare "Lesser" using the InternalKeyCmp
         let lkey = LookupKey::new(key, seq);
 
-        match self.mem.get(&lkey) {
-            (Some(v), _) => return Ok(Some(v)),
-            // deleted entry
-            (None, true) => return Ok(None),
-            // not found entry
-            (None, false) => {}
+        let mut found = self.mem.get(&lkey);
+        if let (None, false) = found {
+            if let Some(imm) = self.imm.as_ref() {
+                found = imm.get(&lkey);
+            }
         }
-
-        if let Some(imm) = self.imm.as_ref() {
-            match imm.get(&lkey) {
-                (Some(v), _) => return Ok(Some(v)),
-                // deleted entry
-                (None, true) => return Ok(None),
-                // not found entry
-                (None, false) => {}
+        match found {
+            (Some(v), _) => {
+                statistics::record_tick(&self.opt, Ticker::MemtableHit, 1);
+                return Ok(Some(v));
+            }
+            // deleted entry
+            (None, true) => {
+                statistics::record_tick(&self.opt, Ticker::MemtableHit, 1);
+                return Ok(None);
             }
+            // not found entry
+            (None, false) => statistics::record_tick(&self.opt, Ticker::MemtableMiss, 1),
         }
 
         let mut do_compaction = false;
@@ -455,17 +1021,31 @@ impl DB {
         {
             let current = self.current();
             let mut current = current.borrow_mut();
-            if let Ok(Some((v, st))) = current.get(lkey.internal_key()) {
-                if current.update_stats(st) {
-                    do_compaction = true;
+            match current.get(lkey.internal_key()) {
+                Ok(Some((v, st))) => {
+                    if current.update_stats(st) {
+                        do_compaction = true;
+                    }
+                    result = Some(v)
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    if self.opt.paranoid_checks {
+                        self.record_bg_error(&e);
+                    }
+                    return Err(e);
                 }
-                result = Some(v)
             }
         }
 
         if do_compaction {
             if let Err(e) = self.maybe_do_compaction() {
-                log!(self.opt.log, "error while doing compaction in get: {}", e);
+                log!(
+                    self.opt.log,
+                    Warn,
+                    "error while doing compaction in get: {}",
+                    e
+                );
             }
         }
         Ok(result)
@@ -486,6 +1066,77 @@ impl DB {
             None
         }
     }
+
+    /// multi_get reads the values for several keys at once. Keys that aren't in the memtables are
+    /// looked up in every table together, which reads the data blocks they need in one batch
+    /// (see `RandomAccess::read_batch()`); UringEnv, for example, submits such a batch with a
+    /// single system call.
+    pub fn multi_get(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let seq = self.vset.borrow().last_seq;
+        self.multi_lookup(seq, keys)
+    }
+
+    /// multi_get_at is like multi_get(), reading the values at or before snapshot.
+    pub fn multi_get_at(
+        &mut self,
+        snapshot: &Snapshot,
+        keys: &[&[u8]],
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        self.multi_lookup(snapshot.sequence(), keys)
+    }
+
+    fn multi_lookup(
+        &mut self,
+        seq: SequenceNumber,
+        keys: &[&[u8]],
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let lkeys: Vec<LookupKey> = keys.iter().map(|k| LookupKey::new(k, seq)).collect();
+        let mut results = vec![None; keys.len()];
+        let mut remaining = vec![];
+        for (i, lkey) in lkeys.iter().enumerate() {
+            let mut found = self.mem.get(lkey);
+            if let (None, false) = found {
+                if let Some(imm) = self.imm.as_ref() {
+                    found = imm.get(lkey);
+                }
+            }
+            match found {
+                (Some(v), _) => {
+                    statistics::record_tick(&self.opt, Ticker::MemtableHit, 1);
+                    results[i] = Some(v);
+                }
+                (None, true) => statistics::record_tick(&self.opt, Ticker::MemtableHit, 1),
+                (None, false) => {
+                    statistics::record_tick(&self.opt, Ticker::MemtableMiss, 1);
+                    remaining.push(i);
+                }
+            }
+        }
+
+        if !remaining.is_empty() {
+            let ikeys: Vec<InternalKey> =
+                remaining.iter().map(|&i| lkeys[i].internal_key()).collect();
+            let found = self.current().borrow().get_many(&ikeys);
+            match found {
+                Ok(values) => {
+                    for (i, v) in remaining.into_iter().zip(values) {
+                        results[i] = v;
+                    }
+                }
+                Err(e) => {
+                    if self.opt.paranoid_checks {
+                        self.record_bg_error(&e);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        for v in results.iter().flatten() {
+            statistics::record_tick(&self.opt, Ticker::BytesRead, v.len() as u64);
+        }
+        Ok(results)
+    }
 }
 
 impl DB {
@@ -505,6 +1156,11 @@ impl DB {
             self.vset.clone(),
             self.merge_iterators()?,
             ss,
+            if self.opt.paranoid_checks {
+                Some(self.bg_error.clone())
+            } else {
+                None
+            },
         ))
     }
 
@@ -552,7 +1208,12 @@ impl DB {
         let current = self.current();
         if current.borrow_mut().record_read_sample(k) {
             if let Err(e) = self.maybe_do_compaction() {
-                log!(self.opt.log, "record_read_sample: compaction failed: {}", e);
+                log!(
+                    self.opt.log,
+                    Warn,
+                    "record_read_sample: compaction failed: {}",
+                    e
+                );
             }
         }
     }
@@ -569,35 +1230,80 @@ impl DB {
         } else if self.mem.len() == 0 {
             Ok(())
         } else {
-            // Create new memtable.
-            let logn = self.vset.borrow_mut().new_file_number();
-            let logf = self
-                .opt
-                .env
-                .open_writable_file(Path::new(&log_file_name(&self.path, logn)));
-            if logf.is_err() {
-                self.vset.borrow_mut().reuse_file_number(logn);
-                Err(logf.err().unwrap())
-            } else {
-                self.log = Some(LogWriter::new(BufWriter::new(logf.unwrap())));
-                self.log_num = Some(logn);
-
-                let mut imm = MemTable::new(self.opt.cmp.clone());
-                mem::swap(&mut imm, &mut self.mem);
-                self.imm = Some(imm);
-                self.maybe_do_compaction()
+            let start_ts = self.opt.env.micros();
+            self.switch_memtable()?;
+            let r = self.maybe_do_compaction();
+            if !force {
+                let micros = self.opt.env.micros() - start_ts;
+                self.notify(|l| l.on_write_stall(micros));
             }
+            r
+        }
+    }
+
+    /// switch_memtable makes the current memtable immutable and starts a new one with a new log
+    /// file. A previous immutable memtable is written to disk first.
+    fn switch_memtable(&mut self) -> Result<()> {
+        if self.imm.is_some() {
+            self.compact_memtable()?;
+        }
+
+        // Create new memtable.
+        let logn = self.vset.borrow_mut().new_file_number();
+        let logf = self
+            .opt
+            .env
+            .open_writable_file(Path::new(&log_file_name(&self.path, logn)));
+        if logf.is_err() {
+            self.vset.borrow_mut().reuse_file_number(logn);
+            Err(logf.err().unwrap())
+        } else {
+            self.log = Some(LogWriter::new(BufWriter::new(logf.unwrap())));
+            self.log_num = Some(logn);
+
+            let mut imm = MemTable::new(self.opt.cmp.clone());
+            mem::swap(&mut imm, &mut self.mem);
+            self.imm = Some(imm);
+            Ok(())
         }
     }
 
+    /// flush_memtable writes the current memtable to a new L0 table, so that its contents don't
+    /// have to be recovered from the log file when the database is opened again. Other levels are
+    /// not compacted.
+    ///
+    /// If `wait` is false, the memtable is only replaced by a new one; it is written to disk by
+    /// the next compaction or memtable flush, and until then it's still recovered from its log
+    /// file if necessary.
+    pub fn flush_memtable(&mut self, wait: bool) -> Result<()> {
+        self.check_writable()?;
+        self.check_bg_error()?;
+        if self.mem.len() > 0 {
+            self.switch_memtable()?;
+        }
+        if wait && self.imm.is_some() {
+            self.compact_memtable()?;
+        }
+        Ok(())
+    }
+
     /// maybe_do_compaction starts a blocking compaction if it makes sense.
     fn maybe_do_compaction(&mut self) -> Result<()> {
-        if self.imm.is_some() {
+        if self.read_only {
+            Ok(())
+        } else if let Err(e) = self.check_bg_error() {
+            Err(e)
+        } else if self.imm.is_some() {
             self.compact_memtable()
         } else if self.vset.borrow().needs_compaction() {
             let c = self.vset.borrow_mut().pick_compaction();
             if let Some(c) = c {
-                self.start_compaction(c)
+                let r = self.start_compaction(c);
+                if r.is_err() && !self.opt.paranoid_checks {
+                    // The inputs are kept, and compacted again later.
+                    return Ok(());
+                }
+                r
             } else {
                 Ok(())
             }
@@ -612,6 +1318,8 @@ impl DB {
     /// Compactions in general will cause the database to find entries more quickly, and take up
     /// less space on disk.
     pub fn compact_range(&mut self, from: &[u8], to: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        self.check_bg_error()?;
         let mut max_level = 1;
         {
             let v = self.vset.borrow().current();
@@ -665,9 +1373,13 @@ impl DB {
 
             let r = self.vset.borrow_mut().log_and_apply(compaction.into_edit());
             if let Err(e) = r {
-                log!(self.opt.log, "trivial move failed: {}", e);
+                log!(self.opt.log, Error, "trivial move failed: {}", e);
+                self.background_error(&e);
                 Err(e)
             } else {
+                if level == 0 {
+                    self.cache.borrow_mut().unpin(num);
+                }
                 log!(
                     self.opt.log,
                     "Moved num={} bytes={} from L{} to L{}",
@@ -689,12 +1401,22 @@ impl DB {
             } else {
                 self.snaps.oldest()
             };
+            let (level, output_level) = (compaction.level(), compaction.output_level());
+            self.notify(|l| l.on_compaction_begin(level, output_level));
             let mut state = CompactionState::new(compaction, smallest);
-            if let Err(e) = self.do_compaction_work(&mut state) {
-                state.cleanup(&self.opt.env, &self.path);
-                log!(self.opt.log, "Compaction work failed: {}", e);
+            let (stats, r) = match self.do_compaction_work(&mut state) {
+                Ok(stats) => (stats, self.install_compaction_results(state)),
+                Err(e) => {
+                    state.cleanup(&self.opt.env, &self.path);
+                    (CompactionStats::default(), Err(e))
+                }
+            };
+            self.notify(|l| l.on_compaction_end(level, output_level, &stats, &r));
+            if let Err(e) = r {
+                log!(self.opt.log, Error, "Compaction failed: {}", e);
+                self.background_error(&e);
+                return Err(e);
             }
-            self.install_compaction_results(state)?;
             log!(
                 self.opt.log,
                 "Compaction finished: {}",
@@ -714,12 +1436,16 @@ impl DB {
         let imm = self.imm.take().unwrap();
         if let Err(e) = self.write_l0_table(&imm, &mut ve, Some(&base.borrow())) {
             self.imm = Some(imm);
+            self.background_error(&e);
             return Err(e);
         }
         ve.set_log_num(self.log_num.unwrap_or(0));
-        self.vset.borrow_mut().log_and_apply(ve)?;
+        if let Err(e) = self.vset.borrow_mut().log_and_apply(ve) {
+            self.background_error(&e);
+            return Err(e);
+        }
         if let Err(e) = self.delete_obsolete_files() {
-            log!(self.opt.log, "Error deleting obsolete files: {}", e);
+            log!(self.opt.log, Warn, "Error deleting obsolete files: {}", e);
         }
         Ok(())
     }
@@ -734,12 +1460,14 @@ impl DB {
         let start_ts = self.opt.env.micros();
         let num = self.vset.borrow_mut().new_file_number();
         log!(self.opt.log, "Start write of L0 table {:06}", num);
+        self.notify(|l| l.on_flush_begin(num));
         let fmd = build_table(&self.path, &self.opt, memt.iter(), num)?;
         log!(self.opt.log, "L0 table {:06} has {} bytes", num, fmd.size);
 
         // Wrote empty table.
         if fmd.size == 0 {
             self.vset.borrow_mut().reuse_file_number(num);
+            self.notify(|l| l.on_flush_end(num, 0, &CompactionStats::default()));
             return Ok(());
         }
 
@@ -747,6 +1475,7 @@ impl DB {
         if let Err(e) = cache_result {
             log!(
                 self.opt.log,
+                Error,
                 "L0 table {:06} not returned by cache: {}",
                 num,
                 e
@@ -765,18 +1494,21 @@ impl DB {
         let mut level = 0;
         if let Some(b) = base {
             level = b.pick_memtable_output_level(
+                &self.opt,
                 parse_internal_key(&fmd.smallest).2,
                 parse_internal_key(&fmd.largest).2,
             );
         }
 
+        self.notify(|l| l.on_table_file_created(num, fmd.size));
+        self.notify(|l| l.on_flush_end(num, level, &stats));
         self.add_stats(level, stats);
         ve.add_file(level, fmd);
 
         Ok(())
     }
 
-    fn do_compaction_work(&mut self, cs: &mut CompactionState) -> Result<()> {
+    fn do_compaction_work(&mut self, cs: &mut CompactionState) -> Result<CompactionStats> {
         {
             let current = self.vset.borrow().current();
             assert!(current.borrow().num_level_files(cs.compaction.level()) > 0);
@@ -805,6 +1537,12 @@ impl DB {
             // TODO: Do we need to do a memtable compaction here? Probably not, in the sequential
             // case.
             assert!(input.current(&mut key, &mut val));
+            if self.opt.rate_limit_compaction_reads {
+                if let Some(ref rl) = self.opt.rate_limiter {
+                    rl.borrow_mut()
+                        .request(IoSource::Compaction, key.len() + val.len());
+                }
+            }
             if cs.compaction.should_stop_before(&key) && cs.builder.is_none() {
                 self.finish_compaction_output(cs, key.clone())?;
             }
@@ -852,7 +1590,8 @@ impl DB {
                 fmd.num = fnum;
 
                 let fname = table_file_name(&self.path, fnum);
-                let f = self.opt.env.open_writable_file(Path::new(&fname))?;
+                let f = open_table_file(&self.opt, Path::new(&fname))?;
+                let f = rate_limited(f, &self.opt.rate_limiter, IoSource::Compaction);
                 let f = Box::new(BufWriter::new(f));
                 cs.builder = Some(TableBuilder::new(self.opt.clone(), f));
                 cs.outputs.push(fmd);
@@ -861,30 +1600,38 @@ impl DB {
                 cs.current_output().smallest = key.clone();
             }
             cs.builder.as_mut().unwrap().add(&key, &val)?;
-            // NOTE: Adjust max file size based on level.
-            if cs.builder.as_ref().unwrap().size_estimate() > self.opt.max_file_size {
+            if cs.builder.as_ref().unwrap().size_estimate() > cs.compaction.max_output_file_size() {
                 self.finish_compaction_output(cs, key.clone())?;
             }
 
             input.advance();
         }
+        if self.opt.paranoid_checks {
+            // Don't drop the remaining input if it couldn't be read.
+            input.status()?;
+        }
 
         if cs.builder.is_some() {
             self.finish_compaction_output(cs, key)?;
         }
 
-        let mut stats = CompactionStats::default();
-        stats.micros = self.opt.env.micros() - start_ts;
+        let mut read = 0;
         for parent in 0..2 {
             for inp in 0..cs.compaction.num_inputs(parent) {
-                stats.read += cs.compaction.input(parent, inp).size;
+                read += cs.compaction.input(parent, inp).size;
             }
         }
-        for output in &cs.outputs {
-            stats.written += output.size;
-        }
+        let stats = CompactionStats {
+            micros: self.opt.env.micros() - start_ts,
+            read,
+            written: cs.outputs.iter().map(|o| o.size).sum(),
+        };
         self.cstats[cs.compaction.level()].add(stats);
-        Ok(())
+        if let Some(ref s) = self.opt.statistics {
+            s.borrow_mut()
+                .record_in_histogram(Histogram::Compaction, stats.micros);
+        }
+        Ok(stats)
     }
 
     fn finish_compaction_output(
@@ -912,7 +1659,7 @@ impl DB {
             // let expression is dangerous).
             let r = self.cache.borrow_mut().get_table(output_num);
             if let Err(e) = r {
-                log!(self.opt.log, "New table can't be read: {}", e);
+                log!(self.opt.log, Error, "New table can't be read: {}", e);
                 return Err(e);
             }
             log!(
@@ -922,6 +1669,7 @@ impl DB {
                 entries,
                 bytes
             );
+            self.notify(|l| l.on_table_file_created(output_num, bytes));
         }
         Ok(())
     }
@@ -937,9 +1685,9 @@ impl DB {
             cs.total_bytes
         );
         cs.compaction.add_input_deletions();
-        let level = cs.compaction.level();
+        let level = cs.compaction.output_level();
         for output in &cs.outputs {
-            cs.compaction.edit().add_file(level + 1, output.clone());
+            cs.compaction.edit().add_file(level, output.clone());
         }
         self.vset
             .borrow_mut()
@@ -986,11 +1734,14 @@ impl CompactionState {
     }
 }
 
-#[derive(Debug, Default)]
-struct CompactionStats {
-    micros: u64,
-    read: usize,
-    written: usize,
+/// CompactionStats describes the work done by flushes and compactions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompactionStats {
+    pub micros: u64,
+    /// Bytes of input tables.
+    pub read: usize,
+    /// Bytes of output tables.
+    pub written: usize,
 }
 
 impl CompactionStats {
@@ -1001,6 +1752,15 @@ impl CompactionStats {
     }
 }
 
+/// Open a new table file written by a flush or compaction.
+fn open_table_file(opt: &Options, path: &Path) -> Result<Box<dyn Write>> {
+    if opt.use_direct_io_for_flush_and_compaction {
+        opt.env.open_direct_writable_file(path)
+    } else {
+        opt.env.open_writable_file(path)
+    }
+}
+
 pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
     dbname: P,
     opt: &Options,
@@ -1018,8 +1778,8 @@ pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
     //
     // TODO: Replace with catch {} when available.
     let r = (|| -> Result<()> {
-        let f = opt.env.open_writable_file(Path::new(&filename))?;
-        let f = BufWriter::new(f);
+        let f = open_table_file(opt, Path::new(&filename))?;
+        let f = BufWriter::new(rate_limited(f, &opt.rate_limiter, IoSource::Flush));
         let mut builder = TableBuilder::new(opt.clone(), f);
         while from.advance() {
             assert!(from.current(&mut kbuf, &mut vbuf));
@@ -1052,7 +1812,40 @@ pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
     Ok(md)
 }
 
-fn log_file_name(db: &Path, num: FileNum) -> PathBuf {
+/// LogCorruptionReporter logs corruptions found while recovering a log file, forwards them to
+/// the user-supplied reporter, and counts them.
+#[derive(Clone)]
+struct LogCorruptionReporter {
+    log: Option<Shared<Box<dyn InfoLogger>>>,
+    user: Option<Shared<Box<dyn CorruptionReporter>>>,
+    log_num: FileNum,
+    corruptions: Rc<Cell<usize>>,
+}
+
+impl CorruptionReporter for LogCorruptionReporter {
+    fn corruption(&mut self, bytes: usize, reason: &Status) {
+        log!(
+            self.log,
+            Warn,
+            "Log file {:06}: dropping {} bytes; {}",
+            self.log_num,
+            bytes,
+            reason
+        );
+        if let Some(ref user) = self.user {
+            user.borrow_mut().corruption(bytes, reason);
+        }
+        self.corruptions.set(self.corruptions.get() + 1);
+    }
+}
+
+/// with_seq returns a copy of the internal key `key` with the sequence number `seq`.
+fn with_seq(key: &[u8], seq: SequenceNumber) -> Vec<u8> {
+    let (typ, _, ukey) = parse_internal_key(key);
+    LookupKey::new_full(ukey, seq, typ).internal_key().to_vec()
+}
+
+pub fn log_file_name(db: &Path, num: FileNum) -> PathBuf {
     db.join(format!("{:06}.log", num))
 }
 
@@ -1062,20 +1855,10 @@ fn lock_file_name(db: &Path) -> PathBuf {
 
 /// open_info_log opens an info log file in the given database. It transparently returns a
 /// /dev/null logger in case the open fails.
-fn open_info_log<E: Env + ?Sized, P: AsRef<Path>>(env: &E, db: P) -> Logger {
-    let db = db.as_ref();
-    let logfilename = db.join("LOG");
-    let oldlogfilename = db.join("LOG.old");
-    let _ = env.mkdir(Path::new(db));
-    if let Ok(e) = env.exists(Path::new(&logfilename)) {
-        if e {
-            let _ = env.rename(Path::new(&logfilename), Path::new(&oldlogfilename));
-        }
-    }
-    if let Ok(w) = env.open_writable_file(Path::new(&logfilename)) {
-        Logger(w)
-    } else {
-        Logger(Box::new(io::sink()))
+fn open_info_log<P: AsRef<Path>>(opt: &Options, db: P) -> Box<dyn InfoLogger> {
+    match FileLogger::open(opt, db.as_ref()) {
+        Ok(l) => Box::new(l),
+        Err(_) => Box::new(infolog::sink(opt.env.clone())),
     }
 }
 
@@ -1142,35 +1925,58 @@ mod tests {
     use super::testutil::{build_db, set_file_to_compact};
     use super::*;
 
+    use crate::env::{Logger, RandomAccess};
     use crate::error::Status;
     use crate::key_types::LookupKey;
     use crate::mem_env::MemEnv;
     use crate::options;
+    use crate::rate_limiter::RateLimiter;
+    use crate::sst_file_writer::SstFileWriter;
+    use crate::statistics::Statistics;
     use crate::test_util::LdbIteratorIter;
     use crate::version::testutil::make_version;
 
+    use std::io::Read;
+
     #[test]
     fn test_db_impl_open_info_log() {
-        let e = MemEnv::new();
+        let opt = options::for_test();
+        let e = &opt.env;
+        // Timestamp and level.
+        let prefix_len = "2026/10/18-12:34:56.789000 INFO ".len();
         {
-            let l = Some(share(open_info_log(&e, "abc")));
+            let l = Some(share(open_info_log(&opt, "abc")));
             assert!(e.exists(&Path::new("abc").join("LOG")).unwrap());
             log!(l, "hello {}", "world");
-            assert_eq!(12, e.size_of(&Path::new("abc").join("LOG")).unwrap());
+            log!(l, Debug, "not written");
+            assert_eq!(
+                prefix_len + 12,
+                e.size_of(&Path::new("abc").join("LOG")).unwrap()
+            );
         }
         {
-            let l = Some(share(open_info_log(&e, "abc")));
-            assert!(e.exists(&Path::new("abc").join("LOG.old")).unwrap());
+            let l = Some(share(open_info_log(&opt, "abc")));
+            let old: Vec<_> = e
+                .children(Path::new("abc"))
+                .unwrap()
+                .into_iter()
+                .filter(|p| p.to_str().unwrap().starts_with("LOG.old."))
+                .collect();
+            assert_eq!(1, old.len());
             assert!(e.exists(&Path::new("abc").join("LOG")).unwrap());
-            assert_eq!(12, e.size_of(&Path::new("abc").join("LOG.old")).unwrap());
+            assert_eq!(
+                prefix_len + 12,
+                e.size_of(&Path::new("abc").join(&old[0])).unwrap()
+            );
             assert_eq!(0, e.size_of(&Path::new("abc").join("LOG")).unwrap());
             log!(l, "something else");
-            log!(l, "and another {}", 1);
+            log!(l, Warn, "and another {}", 1);
 
             let mut s = String::new();
             let mut r = e.open_sequential_file(&Path::new("abc").join("LOG")).unwrap();
             r.read_to_string(&mut s).unwrap();
-            assert_eq!("something else\nand another 1\n", &s);
+            let lines: Vec<&str> = s.lines().map(|l| &l[prefix_len - 5..]).collect();
+            assert_eq!(vec!["INFO something else", "WARN and another 1"], lines);
         }
     }
 
@@ -1456,6 +2262,34 @@ mod tests {
         assert!(env.exists(Path::new(&log_file_name(name, 12))).unwrap());
     }
 
+    #[test]
+    fn test_db_impl_multi_get() {
+        let mut db = build_db().0;
+        db.put(b"xyz", b"123").unwrap();
+        let ss = db.get_snapshot();
+        db.delete(b"eab").unwrap();
+        db.put(b"aaa", b"456").unwrap();
+
+        let mut iter = db.new_iter().unwrap();
+        let mut keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert!(keys.len() > 10);
+        keys.extend([b"eab".to_vec(), b"000".to_vec(), b"zzz".to_vec()]);
+        let keys: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+
+        let values = db.multi_get(&keys).unwrap();
+        assert!(values[..keys.len() - 3].iter().all(Option::is_some));
+        assert_eq!(vec![None, None, None], &values[keys.len() - 3..]);
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(db.get(key), value);
+        }
+        let values = db.multi_get_at(&ss, &keys).unwrap();
+        assert_eq!(Some(b"val2".to_vec()), values[keys.len() - 3]);
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(db.get_at(&ss, key).unwrap(), value);
+        }
+        assert!(db.multi_get(&[]).unwrap().is_empty());
+    }
+
     #[test]
     fn test_db_impl_get_from_table_with_snapshot() {
         let mut db = build_db().0;
@@ -1705,4 +2539,968 @@ mod tests {
             assert_eq!(None, db.get_at(&ss, b"xx2").unwrap());
         }
     }
+
+    #[test]
+    fn test_db_impl_ingest_external_file() {
+        let opt = options::for_test();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"aaa", b"old").unwrap();
+        db.put(b"ccc", b"old").unwrap();
+        let ss = db.get_snapshot();
+
+        {
+            let mut w = SstFileWriter::create(opt.clone(), "ext1.sst").unwrap();
+            w.put(b"aaa", b"new").unwrap();
+            w.put(b"bbb", b"x").unwrap();
+            w.finish().unwrap();
+            let mut w = SstFileWriter::create(opt.clone(), "ext2.sst").unwrap();
+            w.delete(b"ccc").unwrap();
+            w.put(b"ddd", b"y").unwrap();
+            w.finish().unwrap();
+            let mut w = SstFileWriter::create(opt.clone(), "ext3.sst").unwrap();
+            w.put(b"bba", b"z").unwrap();
+            w.finish().unwrap();
+        }
+
+        // ext3 overlaps ext1.
+        let r = db.ingest_external_file(&["ext2.sst", "ext1.sst", "ext3.sst"]);
+        assert_eq!(StatusCode::InvalidArgument, r.err().unwrap().code);
+        assert_eq!(Some(b"old".to_vec()), db.get(b"aaa"));
+
+        let read_file = |p: &Path| {
+            let mut contents = vec![];
+            let mut f = opt.env.open_sequential_file(p).unwrap();
+            f.read_to_end(&mut contents).unwrap();
+            contents
+        };
+        let ext2 = read_file(Path::new("ext2.sst"));
+        db.ingest_external_file(&["ext2.sst", "ext1.sst"]).unwrap();
+        // The file has been linked as it is.
+        let tables: Vec<_> = opt
+            .env
+            .children(Path::new("db"))
+            .unwrap()
+            .into_iter()
+            .filter(|f| parse_file_name(f).unwrap().1 == FileType::Table)
+            .map(|f| read_file(&Path::new("db").join(f)))
+            .collect();
+        assert!(tables.contains(&ext2));
+        // Replacing the original file doesn't affect the database.
+        {
+            let mut w = SstFileWriter::create(opt.clone(), "ext1.sst").unwrap();
+            w.put(b"aaa", b"other").unwrap();
+            w.finish().unwrap();
+        }
+        assert_eq!(Some(b"new".to_vec()), db.get(b"aaa"));
+        assert_eq!(Some(b"x".to_vec()), db.get(b"bbb"));
+        assert_eq!(None, db.get(b"ccc"));
+        assert_eq!(Some(b"y".to_vec()), db.get(b"ddd"));
+        assert_eq!(Some(b"old".to_vec()), db.get_at(&ss, b"aaa").unwrap());
+        assert_eq!(Some(b"old".to_vec()), db.get_at(&ss, b"ccc").unwrap());
+        assert_eq!(None, db.get_at(&ss, b"bbb").unwrap());
+
+        // The memtable overlapped, so it was written to L0 before the ingested files were placed
+        // above it.
+        {
+            let v = db.current();
+            let v = v.borrow();
+            let files: usize = (0..NUM_LEVELS).map(|l| v.num_level_files(l)).sum();
+            assert_eq!(3, files);
+        }
+
+        // ext3 falls into the range of ext1, so it has to be placed above it.
+        db.ingest_external_file(&["ext3.sst"]).unwrap();
+        assert_eq!(1, db.current().borrow().num_level_files(0));
+        assert_eq!(Some(b"z".to_vec()), db.get(b"bba"));
+        db.put(b"bba", b"zz").unwrap();
+        assert_eq!(Some(b"zz".to_vec()), db.get(b"bba"));
+
+        drop(db);
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(Some(b"new".to_vec()), db.get(b"aaa"));
+        assert_eq!(None, db.get(b"ccc"));
+        assert_eq!(Some(b"zz".to_vec()), db.get(b"bba"));
+
+        db.compact_range(b"aaa", b"zzz").unwrap();
+        assert_eq!(Some(b"new".to_vec()), db.get(b"aaa"));
+        assert_eq!(Some(b"x".to_vec()), db.get(b"bbb"));
+        assert_eq!(None, db.get(b"ccc"));
+        assert_eq!(Some(b"y".to_vec()), db.get(b"ddd"));
+    }
+
+    #[test]
+    fn test_db_impl_ingest_external_file_copy() {
+        let mut opt = options::for_test();
+        // FailingEnv doesn't support hard links.
+        opt.env = Rc::new(Box::new(FailingEnv {
+            env: opt.env.clone(),
+            fail: Rc::new(Cell::new(false)),
+        }));
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"aaa", b"old").unwrap();
+        db.flush_memtable(true).unwrap();
+        {
+            let mut w = SstFileWriter::create(opt.clone(), "ext.sst").unwrap();
+            w.put(b"aaa", b"new").unwrap();
+            w.finish().unwrap();
+        }
+        db.ingest_external_file(&["ext.sst"]).unwrap();
+        opt.env.delete(Path::new("ext.sst")).unwrap();
+        assert_eq!(Some(b"new".to_vec()), db.get(b"aaa"));
+        let mut iter = db.new_iter().unwrap();
+        assert_eq!(1, LdbIteratorIter::wrap(&mut iter).count());
+
+        drop(iter);
+        drop(db);
+        let mut db = DB::open("db", opt).unwrap();
+        assert_eq!(Some(b"new".to_vec()), db.get(b"aaa"));
+    }
+
+    #[test]
+    fn test_db_impl_get_updates_since() {
+        let mut opt = options::for_test();
+        opt.wal_size_limit = 1 << 20;
+        let mut db = DB::open("db", opt.clone()).unwrap();
+
+        let mut wb = WriteBatch::new();
+        wb.put(b"aaa", b"1");
+        wb.put(b"bbb", b"2");
+        db.write(wb, false).unwrap();
+        db.delete(b"aaa").unwrap();
+        // Writes the memtable to a table; the old log file is retained.
+        db.make_room_for_write(true).unwrap();
+        db.put(b"ccc", b"3").unwrap();
+        assert_eq!(1, db.retained_logs.len());
+
+        let updates: Vec<_> = db
+            .get_updates_since(2)
+            .unwrap()
+            .map(|r| {
+                let (seq, batch) = r.unwrap();
+                let entries: Vec<_> = batch
+                    .iter()
+                    .map(|(k, v)| (k.to_vec(), v.map(|v| v.to_vec())))
+                    .collect();
+                (seq, entries)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    1,
+                    vec![
+                        (b"aaa".to_vec(), Some(b"1".to_vec())),
+                        (b"bbb".to_vec(), Some(b"2".to_vec()))
+                    ]
+                ),
+                (3, vec![(b"aaa".to_vec(), None)]),
+                (4, vec![(b"ccc".to_vec(), Some(b"3".to_vec()))]),
+            ],
+            updates
+        );
+        assert_eq!(1, db.get_updates_since(4).unwrap().count());
+        assert_eq!(0, db.get_updates_since(5).unwrap().count());
+
+        // Reopening recovers from the current log file only, and keeps the old one. Its age
+        // still counts from when it was written.
+        let retained_since = db.retained_logs.values().next().cloned().unwrap();
+        drop(db);
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(3, db.get_updates_since(0).unwrap().count());
+        assert_eq!(1, db.retained_logs.len());
+        assert!(*db.retained_logs.values().next().unwrap() <= retained_since);
+
+        // A smaller budget causes the old files to be deleted.
+        db.opt.wal_size_limit = 1;
+        db.delete_obsolete_files().unwrap();
+        assert!(db.retained_logs.is_empty());
+        assert_eq!(1, db.get_updates_since(0).unwrap().count());
+    }
+
+    /// Returns options for a database whose only log file has been modified by `corrupt`.
+    fn build_db_with_corrupted_log<F: Fn(&mut Vec<u8>)>(corrupt: F) -> Options {
+        let mut opt = options::for_test();
+        opt.reuse_logs = false;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..100 {
+                db.put(format!("key{:03}", i).as_bytes(), &[b'x'; 1000])
+                    .unwrap();
+            }
+            db.flush().unwrap();
+        }
+        let log = log_file_name(Path::new("db"), 3);
+        let mut contents = vec![];
+        opt.env
+            .open_sequential_file(&log)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        corrupt(&mut contents);
+        opt.env
+            .open_writable_file(&log)
+            .unwrap()
+            .write_all(&contents)
+            .unwrap();
+        opt
+    }
+
+    struct CountingReporter(Rc<Cell<usize>>);
+
+    impl CorruptionReporter for CountingReporter {
+        fn corruption(&mut self, _: usize, _: &Status) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_db_impl_wal_recovery_modes() {
+        use WalRecoveryMode::*;
+        // Damage a record in the first block of the log file.
+        let corrupt_middle = |c: &mut Vec<u8>| c[20000] ^= 0xff;
+        let truncate_tail = |c: &mut Vec<u8>| {
+            let len = c.len();
+            c.truncate(len - 100)
+        };
+
+        for mode in [AbsoluteConsistency, TolerateCorruptedTailRecords].iter() {
+            let mut opt = build_db_with_corrupted_log(corrupt_middle);
+            opt.wal_recovery_mode = *mode;
+            let e = DB::open("db", opt).err().unwrap();
+            assert_eq!(StatusCode::Corruption, e.code);
+        }
+
+        let mut opt = build_db_with_corrupted_log(corrupt_middle);
+        opt.wal_recovery_mode = PointInTimeRecovery;
+        let mut db = DB::open("db", opt).unwrap();
+        assert!(db.get(b"key000").is_some());
+        assert!(db.get(b"key031").is_none());
+        assert!(db.get(b"key099").is_none());
+
+        let mut opt = build_db_with_corrupted_log(corrupt_middle);
+        let count = Rc::new(Cell::new(0));
+        opt.wal_recovery_mode = SkipAnyCorruptedRecords;
+        opt.corruption_reporter = Some(share(Box::new(CountingReporter(count.clone()))));
+        let mut db = DB::open("db", opt).unwrap();
+        // The damaged block, and the tail of the record continuing in the next block.
+        assert_eq!(2, count.get());
+        assert!(db.get(b"key000").is_some());
+        assert!(db.get(b"key031").is_none());
+        assert!(db.get(b"key099").is_some());
+
+        let mut opt = build_db_with_corrupted_log(truncate_tail);
+        opt.wal_recovery_mode = AbsoluteConsistency;
+        assert!(DB::open("db", opt).is_err());
+
+        let mut opt = build_db_with_corrupted_log(truncate_tail);
+        opt.wal_recovery_mode = TolerateCorruptedTailRecords;
+        let mut db = DB::open("db", opt).unwrap();
+        assert!(db.get(b"key098").is_some());
+        assert!(db.get(b"key099").is_none());
+    }
+
+    #[test]
+    fn test_db_impl_wal_recovery_skips_later_logs() {
+        use WalRecoveryMode::*;
+        // A later log file with an intact record, as left behind after a crash.
+        let add_later_log = |opt: &Options| {
+            let f = opt
+                .env
+                .open_writable_file(&log_file_name(Path::new("db"), 4))
+                .unwrap();
+            let mut wb = WriteBatch::new();
+            wb.put(b"later", b"value");
+            let mut lw = LogWriter::new(f);
+            lw.add_record(&wb.encode(1000)).unwrap();
+            lw.flush().unwrap();
+        };
+
+        // By default, the rest of the damaged log is dropped, but later logs are replayed.
+        let opt = build_db_with_corrupted_log(|c| c[20000] ^= 0xff);
+        assert_eq!(DropCorruptedLogTails, opt.wal_recovery_mode);
+        add_later_log(&opt);
+        let mut db = DB::open("db", opt).unwrap();
+        assert!(db.get(b"key000").is_some());
+        assert!(db.get(b"key099").is_none());
+        assert_eq!(Some(b"value".to_vec()), db.get(b"later"));
+
+        // Point-in-time recovery recovers nothing after the corruption.
+        let mut opt = build_db_with_corrupted_log(|c| c[20000] ^= 0xff);
+        opt.wal_recovery_mode = PointInTimeRecovery;
+        add_later_log(&opt);
+        let mut db = DB::open("db", opt).unwrap();
+        assert!(db.get(b"key000").is_some());
+        assert!(db.get(b"key099").is_none());
+        assert!(db.get(b"later").is_none());
+
+        let mut opt = build_db_with_corrupted_log(|c| c[20000] ^= 0xff);
+        opt.wal_recovery_mode = SkipAnyCorruptedRecords;
+        add_later_log(&opt);
+        let mut db = DB::open("db", opt).unwrap();
+        assert!(db.get(b"key099").is_some());
+        assert_eq!(Some(b"value".to_vec()), db.get(b"later"));
+    }
+
+    #[test]
+    fn test_db_impl_paranoid_checks() {
+        let mut opt = build_db_with_corrupted_log(|c| c[20000] ^= 0xff);
+        opt.paranoid_checks = true;
+        opt.wal_recovery_mode = WalRecoveryMode::SkipAnyCorruptedRecords;
+        assert!(DB::open("db", opt).is_err());
+
+        let mut opt = options::for_test();
+        opt.paranoid_checks = true;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..100 {
+                db.put(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+            }
+            db.compact_range(b"key000", b"key099").unwrap();
+        }
+        // Damage the first block of the only table.
+        let table = opt
+            .env
+            .children(Path::new("db"))
+            .unwrap()
+            .into_iter()
+            .find(|f| parse_file_name(f).unwrap().1 == FileType::Table)
+            .unwrap();
+        let table = Path::new("db").join(table);
+        let mut contents = vec![];
+        opt.env
+            .open_sequential_file(&table)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents[10] ^= 0xff;
+        opt.env
+            .open_writable_file(&table)
+            .unwrap()
+            .write_all(&contents)
+            .unwrap();
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        let ss = db.get_snapshot();
+        assert!(db.get_at(&ss, b"key000").is_err());
+        // The database is in an error state now.
+        assert!(db.put(b"abc", b"def").is_err());
+        assert!(db.compact_range(b"a", b"z").is_err());
+        drop(db);
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        let mut iter = db.new_iter().unwrap();
+        assert_eq!(0, LdbIteratorIter::wrap(&mut iter).count());
+        assert_eq!(StatusCode::Corruption, iter.status().err().unwrap().code);
+        assert!(db.put(b"abc", b"def").is_err());
+    }
+
+    #[test]
+    fn test_db_impl_missing_table_without_paranoid_checks() {
+        let opt = options::for_test();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        for i in 0..100 {
+            db.put(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        db.compact_range(b"key000", b"key099").unwrap();
+        for f in opt.env.children(Path::new("db")).unwrap() {
+            let (num, typ) = parse_file_name(&f).unwrap();
+            if typ == FileType::Table {
+                db.cache.borrow_mut().evict(num).unwrap();
+                opt.env.delete(&Path::new("db").join(f)).unwrap();
+            }
+        }
+
+        // The missing table is skipped, and the database stays usable.
+        let mut iter = db.new_iter().unwrap();
+        assert_eq!(0, LdbIteratorIter::wrap(&mut iter).count());
+        assert!(iter.status().is_ok());
+        assert!(db.get(b"key000").is_none());
+        db.put(b"abc", b"def").unwrap();
+        db.flush().unwrap();
+        assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+    }
+
+    #[test]
+    fn test_db_impl_write_disable_wal() {
+        let mut opt = options::for_test();
+        opt.reuse_logs = false;
+        let wopt = WriteOptions {
+            disable_wal: true,
+            ..Default::default()
+        };
+        let write = |db: &mut DB, k: &[u8]| {
+            let mut wb = WriteBatch::new();
+            wb.put(k, b"value");
+            db.write_opt(wb, &wopt)
+        };
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            let log_size = opt.env.size_of(&log_file_name(&db.path, 3)).unwrap();
+            write(&mut db, b"abc").unwrap();
+            write(&mut db, b"abd").unwrap();
+            db.put(b"xyz", b"logged").unwrap();
+            db.flush().unwrap();
+            assert!(opt.env.size_of(&log_file_name(&db.path, 3)).unwrap() > log_size);
+            assert_eq!(Some(b"value".to_vec()), db.get(b"abc"));
+
+            // Write the memtable to L0.
+            db.make_room_for_write(true).unwrap();
+            write(&mut db, b"abe").unwrap();
+        }
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(Some(b"value".to_vec()), db.get(b"abc"));
+        assert_eq!(Some(b"value".to_vec()), db.get(b"abd"));
+        assert_eq!(Some(b"logged".to_vec()), db.get(b"xyz"));
+        // Not written to L0 and not logged.
+        assert_eq!(None, db.get(b"abe"));
+    }
+
+    #[test]
+    fn test_db_impl_flush_memtable() {
+        let mut opt = options::for_test();
+        opt.reuse_logs = false;
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        // Memtables may be written to levels above L0 if they don't overlap with other tables.
+        let num_tables = |db: &DB| {
+            let v = db.current();
+            let v = v.borrow();
+            (0..NUM_LEVELS).map(|l| v.num_level_files(l)).sum::<usize>()
+        };
+
+        db.put(b"abc", b"def").unwrap();
+        db.flush_memtable(false).unwrap();
+        assert!(db.imm.is_some());
+        assert_eq!(0, db.mem.len());
+        assert_eq!(0, num_tables(&db));
+        assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+
+        // The pending memtable is written before the next one is switched.
+        db.put(b"abd", b"def").unwrap();
+        db.flush_memtable(false).unwrap();
+        assert_eq!(1, num_tables(&db));
+
+        db.flush_memtable(true).unwrap();
+        assert!(db.imm.is_none());
+        assert_eq!(2, num_tables(&db));
+        // Nothing to do.
+        db.flush_memtable(true).unwrap();
+        assert_eq!(2, num_tables(&db));
+
+        db.put(b"abe", b"def").unwrap();
+        opt.flush_memtable_on_close = true;
+        db.opt = opt.clone();
+        db.close().unwrap();
+        drop(db);
+
+        // Only the current (empty) log file is left.
+        let logs = opt
+            .env
+            .children(Path::new("db"))
+            .unwrap()
+            .into_iter()
+            .filter(|f| parse_file_name(f).unwrap().1 == FileType::Log)
+            .collect::<Vec<_>>();
+        assert_eq!(1, logs.len());
+        let db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(3, num_tables(&db));
+    }
+
+    #[test]
+    fn test_db_impl_open_invalid_options() {
+        let mut opt = options::for_test();
+        opt.max_bytes_for_level_multiplier = 0.5;
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            DB::open("db", opt).err().unwrap().code
+        );
+        let mut opt = options::for_test();
+        opt.max_mem_compaction_level = NUM_LEVELS;
+        assert!(DB::open("db", opt).is_err());
+    }
+
+    #[test]
+    fn test_db_impl_universal_compaction() {
+        let mut opt = options::for_test();
+        opt.compaction_style = CompactionStyle::Universal;
+        opt.write_buffer_size = 1024;
+        opt.log = None;
+        let mut db = DB::open("db", opt.clone()).unwrap();
+
+        for i in 0..2000 {
+            let k = format!("key{:04}", i % 500);
+            db.put(k.as_bytes(), format!("value{}", i).as_bytes())
+                .unwrap();
+        }
+        for i in 0..100 {
+            db.delete(format!("key{:04}", i).as_bytes()).unwrap();
+        }
+        // Memtable flushes don't trigger further compactions.
+        while db.vset.borrow().needs_compaction() {
+            db.maybe_do_compaction().unwrap();
+        }
+
+        let check = |db: &mut DB| {
+            let v = db.current();
+            let v = v.borrow();
+            assert!(v.files[0].len() < opt.level0_file_num_compaction_trigger);
+            let deeper: usize = (1..NUM_LEVELS).map(|l| v.num_level_files(l)).sum();
+            assert_eq!(0, deeper);
+            drop(v);
+            assert_eq!(None, db.get(b"key0050"));
+            assert_eq!(Some(b"value1999".to_vec()), db.get(b"key0499"));
+            assert_eq!(Some(b"value1600".to_vec()), db.get(b"key0100"));
+            let mut iter = db.new_iter().unwrap();
+            assert_eq!(400, LdbIteratorIter::wrap(&mut iter).count());
+        };
+        check(&mut db);
+        assert!(db.cstats[0].written > 0);
+
+        db.compact_range(b"key0000", b"key0499").unwrap();
+        check(&mut db);
+        assert_eq!(1, db.current().borrow().files[0].len());
+    }
+
+    #[test]
+    fn test_db_impl_rate_limiter() {
+        // Returns the bytes charged to the flush and compaction budgets.
+        let run = |reads: bool| {
+            let mut opt = options::for_test();
+            opt.max_mem_compaction_level = 0;
+            opt.rate_limit_compaction_reads = reads;
+            let rl = share(RateLimiter::new(opt.env.clone(), 0, 0));
+            opt.rate_limiter = Some(rl.clone());
+            let mut db = DB::open("db", opt).unwrap();
+
+            db.put(b"abc", b"def").unwrap();
+            db.flush_memtable(true).unwrap();
+            db.put(b"abd", b"def").unwrap();
+            db.flush_memtable(true).unwrap();
+            let v = db.current();
+            let l0_size: usize = v.borrow().files[0].iter().map(|f| f.borrow().size).sum();
+            assert_eq!(l0_size, rl.borrow().total_bytes(IoSource::Flush));
+            assert_eq!(0, rl.borrow().total_bytes(IoSource::Compaction));
+
+            db.compact_range(b"abc", b"abd").unwrap();
+            assert_eq!(Some(b"def".to_vec()), db.get(b"abd"));
+            let rl = rl.borrow();
+            (
+                rl.total_bytes(IoSource::Flush),
+                rl.total_bytes(IoSource::Compaction),
+            )
+        };
+
+        let (flushed, compacted) = run(false);
+        assert!(compacted > 0);
+        // Reads are charged to the compaction budget, too.
+        let (flushed_with_reads, compacted_with_reads) = run(true);
+        assert_eq!(flushed, flushed_with_reads);
+        assert!(compacted < compacted_with_reads);
+    }
+
+    struct RecordingListener(Shared<Vec<String>>);
+
+    impl EventListener for RecordingListener {
+        fn on_flush_begin(&self, table: FileNum) {
+            self.0.borrow_mut().push(format!("flush {}", table));
+        }
+        fn on_flush_end(&self, table: FileNum, level: usize, stats: &CompactionStats) {
+            let event = format!("flushed {} to L{}: {}", table, level, stats.written > 0);
+            self.0.borrow_mut().push(event);
+        }
+        fn on_compaction_begin(&self, level: usize, output_level: usize) {
+            let event = format!("compact L{} to L{}", level, output_level);
+            self.0.borrow_mut().push(event);
+        }
+        fn on_compaction_end(
+            &self,
+            level: usize,
+            _: usize,
+            stats: &CompactionStats,
+            result: &Result<()>,
+        ) {
+            let (read, ok) = (stats.read > 0, result.is_ok());
+            let event = format!("compacted L{}: {} {}", level, read, ok);
+            self.0.borrow_mut().push(event);
+        }
+        fn on_table_file_created(&self, table: FileNum, _: usize) {
+            self.0.borrow_mut().push(format!("created {}", table));
+        }
+        fn on_table_file_deleted(&self, table: FileNum) {
+            self.0.borrow_mut().push(format!("deleted {}", table));
+        }
+        fn on_write_stall(&self, _: u64) {
+            self.0.borrow_mut().push("stall".to_string());
+        }
+        fn on_background_error(&self, e: &Status) {
+            self.0.borrow_mut().push(format!("error {:?}", e.code));
+        }
+    }
+
+    #[test]
+    fn test_db_impl_event_listener() {
+        let mut opt = options::for_test();
+        opt.max_mem_compaction_level = 0;
+        opt.write_buffer_size = 100;
+        let events = share(vec![]);
+        opt.listeners
+            .push(Rc::new(Box::new(RecordingListener(events.clone()))));
+        let mut db = DB::open("db", opt).unwrap();
+        let take = || events.replace(vec![]);
+
+        // The memtable is flushed before the next write is applied.
+        db.put(b"abc", &[0; 100]).unwrap();
+        db.put(b"abd", b"def").unwrap();
+        assert_eq!(
+            vec!["flush 5", "created 5", "flushed 5 to L0: true", "stall"],
+            take()
+        );
+
+        db.flush_memtable(true).unwrap();
+        db.compact_range(b"abc", b"abd").unwrap();
+        let events = take();
+        assert_eq!(
+            vec![
+                "flush 7",
+                "created 7",
+                "flushed 7 to L0: true",
+                "compact L0 to L1",
+                "created 8",
+                "compacted L0: true true",
+            ],
+            events[..6].to_vec()
+        );
+        assert!(events.contains(&"deleted 5".to_string()));
+        assert!(events.contains(&"deleted 7".to_string()));
+
+        db.record_bg_error(&Status::new(StatusCode::Corruption, "bad block"));
+        db.record_bg_error(&Status::new(StatusCode::IOError, "ignored"));
+        assert_eq!(vec!["error Corruption"], take());
+    }
+
+    /// FailingEnv fails to create table files while `fail` is set.
+    struct FailingEnv {
+        env: Rc<Box<dyn Env>>,
+        fail: Rc<Cell<bool>>,
+    }
+
+    impl FailingEnv {
+        fn check(&self, p: &Path) -> Result<()> {
+            if self.fail.get() && p.extension().map_or(false, |e| e == "ldb") {
+                err(StatusCode::IOError, "can't create table")
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Env for FailingEnv {
+        fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+            self.env.open_sequential_file(p)
+        }
+        fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+            self.env.open_random_access_file(p)
+        }
+        fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+            self.check(p)?;
+            self.env.open_writable_file(p)
+        }
+        fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+            self.check(p)?;
+            self.env.open_appendable_file(p)
+        }
+        fn exists(&self, p: &Path) -> Result<bool> {
+            self.env.exists(p)
+        }
+        fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+            self.env.children(p)
+        }
+        fn size_of(&self, p: &Path) -> Result<usize> {
+            self.env.size_of(p)
+        }
+        fn delete(&self, p: &Path) -> Result<()> {
+            self.env.delete(p)
+        }
+        fn mkdir(&self, p: &Path) -> Result<()> {
+            self.env.mkdir(p)
+        }
+        fn rmdir(&self, p: &Path) -> Result<()> {
+            self.env.rmdir(p)
+        }
+        fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+            self.env.rename(old, new)
+        }
+        fn lock(&self, p: &Path) -> Result<FileLock> {
+            self.env.lock(p)
+        }
+        fn unlock(&self, l: FileLock) -> Result<()> {
+            self.env.unlock(l)
+        }
+        fn new_logger(&self, p: &Path) -> Result<Logger> {
+            self.env.new_logger(p)
+        }
+        fn micros(&self) -> u64 {
+            self.env.micros()
+        }
+        fn sleep_for(&self, micros: u32) {
+            self.env.sleep_for(micros)
+        }
+    }
+
+    #[test]
+    fn test_db_impl_event_listener_background_errors() {
+        for paranoid in [false, true].iter() {
+            let mut opt = options::for_test();
+            let fail = Rc::new(Cell::new(false));
+            opt.env = Rc::new(Box::new(FailingEnv {
+                env: opt.env.clone(),
+                fail: fail.clone(),
+            }));
+            opt.paranoid_checks = *paranoid;
+            opt.max_mem_compaction_level = 0;
+            let events = share(vec![]);
+            opt.listeners
+                .push(Rc::new(Box::new(RecordingListener(events.clone()))));
+            let mut db = DB::open("db", opt).unwrap();
+            let take = || events.replace(vec![]);
+
+            db.put(b"abc", b"def").unwrap();
+            db.flush_memtable(true).unwrap();
+            db.put(b"abd", b"def").unwrap();
+            db.flush_memtable(true).unwrap();
+            take();
+
+            // The compaction's output table can't be created.
+            fail.set(true);
+            assert!(db.compact_range(b"abc", b"abd").is_err());
+            let events = take();
+            assert!(events.contains(&"compacted L0: false false".to_string()));
+            assert_eq!(Some(&"error IOError".to_string()), events.last());
+            // The inputs are still there.
+            assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+            assert_eq!(Some(b"def".to_vec()), db.get(b"abd"));
+            assert_eq!(2, db.current().borrow().num_level_files(0));
+
+            fail.set(false);
+            if *paranoid {
+                assert!(db.put(b"abe", b"def").is_err());
+                continue;
+            }
+
+            // The memtable's table can't be created.
+            db.put(b"abe", b"def").unwrap();
+            fail.set(true);
+            assert!(db.flush_memtable(true).is_err());
+            let events = take();
+            assert_eq!(2, events.len());
+            assert_eq!("error IOError", events[1]);
+            fail.set(false);
+            db.flush_memtable(true).unwrap();
+            assert_eq!(Some(b"def".to_vec()), db.get(b"abe"));
+        }
+    }
+
+    #[test]
+    fn test_db_impl_statistics() {
+        let mut opt = options::for_test();
+        opt.max_mem_compaction_level = 0;
+        let stats = share(Statistics::new());
+        opt.statistics = Some(stats.clone());
+        let mut db = DB::open("db", opt).unwrap();
+
+        db.put(b"abc", b"def").unwrap();
+        db.delete(b"abd").unwrap();
+        assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+        assert_eq!(None, db.get(b"abd"));
+        assert_eq!(None, db.get(b"abe"));
+        {
+            let s = stats.borrow();
+            assert_eq!(2, s.ticker(Ticker::MemtableHit));
+            assert_eq!(1, s.ticker(Ticker::MemtableMiss));
+            assert_eq!(3, s.ticker(Ticker::BytesRead));
+            assert!(s.ticker(Ticker::BytesWritten) > 6);
+            assert_eq!(2, s.histogram(Histogram::Write).count());
+            assert_eq!(3, s.histogram(Histogram::Get).count());
+        }
+
+        db.flush_memtable(true).unwrap();
+        stats.borrow_mut().reset();
+        assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+        assert_eq!(Some(b"def".to_vec()), db.get(b"abc"));
+        assert_eq!(None, db.get(b"abcd"));
+        {
+            let s = stats.borrow();
+            assert_eq!(3, s.ticker(Ticker::MemtableMiss));
+            assert_eq!(2, s.found_in_level(0));
+            assert_eq!(1, s.ticker(Ticker::BlockCacheMiss));
+            assert_eq!(1, s.ticker(Ticker::BlockCacheHit));
+            assert_eq!(2, s.ticker(Ticker::BloomFilterPositive));
+            assert_eq!(1, s.ticker(Ticker::BloomFilterUseful));
+        }
+
+        db.put(b"abd", b"def").unwrap();
+        db.flush_memtable(true).unwrap();
+        db.compact_range(b"abc", b"abd").unwrap();
+        let mut iter = db.new_iter().unwrap();
+        iter.seek(b"abd");
+        assert!(iter.valid());
+        let s = stats.borrow();
+        assert!(s.histogram(Histogram::Compaction).count() > 0);
+        assert_eq!(1, s.histogram(Histogram::Seek).count());
+        assert!(s.to_string().contains("seek.micros: count=1 "));
+    }
+
+    #[test]
+    fn test_db_impl_open_read_only() {
+        let mut opt = options::for_test();
+        opt.log = None;
+        opt.reuse_logs = false;
+        opt.write_buffer_size = 512;
+
+        assert!(DB::open_read_only("db", opt.clone()).is_err());
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..50 {
+                db.put(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+            }
+            db.delete(b"key049").unwrap();
+            db.flush().unwrap();
+        }
+        let mut files = opt.env.children(Path::new("db")).unwrap();
+        files.sort();
+        let log_size = opt.env.size_of(&Path::new("db").join("LOG")).unwrap();
+
+        let mut db1 = DB::open_read_only("db", opt.clone()).unwrap();
+        let mut db2 = DB::open_read_only("db", opt.clone()).unwrap();
+        for db in [&mut db1, &mut db2].iter_mut() {
+            assert_eq!(Some(b"value".to_vec()), db.get(b"key000"));
+            assert_eq!(Some(b"value".to_vec()), db.get(b"key048"));
+            assert_eq!(None, db.get(b"key049"));
+            let mut iter = db.new_iter().unwrap();
+            assert_eq!(49, LdbIteratorIter::wrap(&mut iter).count());
+
+            let want = StatusCode::NotSupported;
+            assert_eq!(want, db.put(b"a", b"b").err().unwrap().code);
+            assert_eq!(want, db.delete(b"a").err().unwrap().code);
+            assert_eq!(want, db.flush().err().unwrap().code);
+            assert_eq!(want, db.compact_range(b"a", b"z").err().unwrap().code);
+            db.close().unwrap();
+        }
+
+        let mut files_after = opt.env.children(Path::new("db")).unwrap();
+        files_after.sort();
+        assert_eq!(files, files_after);
+        assert_eq!(
+            log_size,
+            opt.env.size_of(&Path::new("db").join("LOG")).unwrap()
+        );
+
+        // A writer can still open the database.
+        drop(db1);
+        drop(db2);
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(Some(b"value".to_vec()), db.get(b"key048"));
+        drop(db);
+
+        let mut invalid = opt.clone();
+        invalid.max_bytes_for_level_base = 0;
+        let want = StatusCode::InvalidArgument;
+        let r = DB::open_read_only("db", invalid.clone());
+        assert_eq!(want, r.err().unwrap().code);
+        let r = DB::open_as_secondary("db", invalid);
+        assert_eq!(want, r.err().unwrap().code);
+    }
+
+    #[test]
+    fn test_db_impl_secondary() {
+        let opt = options::for_test();
+        let mut primary = DB::open("db", opt.clone()).unwrap();
+        primary.put(b"aaa", b"1").unwrap();
+        primary.put(b"bbb", b"2").unwrap();
+        primary.flush().unwrap();
+
+        let mut secondary = DB::open_as_secondary("db", opt.clone()).unwrap();
+        assert_eq!(Some(b"1".to_vec()), secondary.get(b"aaa"));
+        assert_eq!(Some(b"2".to_vec()), secondary.get(b"bbb"));
+        assert!(secondary.put(b"ccc", b"3").is_err());
+
+        primary.put(b"ccc", b"3").unwrap();
+        primary.flush().unwrap();
+        assert_eq!(None, secondary.get(b"ccc"));
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(Some(b"3".to_vec()), secondary.get(b"ccc"));
+
+        // The memtable is written to a table and a new log file is started.
+        primary.make_room_for_write(true).unwrap();
+        primary.delete(b"aaa").unwrap();
+        primary.put(b"ddd", b"4").unwrap();
+        primary.flush().unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        let files = secondary.current().borrow().files.iter().flatten().count();
+        assert_eq!(1, files);
+        assert_eq!(None, secondary.get(b"aaa"));
+        assert_eq!(Some(b"2".to_vec()), secondary.get(b"bbb"));
+        assert_eq!(Some(b"4".to_vec()), secondary.get(b"ddd"));
+        assert_eq!(5, secondary.vset.borrow().last_seq);
+
+        // The primary starts a new manifest.
+        drop(primary);
+        let mut opt2 = opt.clone();
+        opt2.reuse_manifest = false;
+        let mut primary = DB::open("db", opt2).unwrap();
+        primary.put(b"eee", b"5").unwrap();
+        primary.compact_range(b"aaa", b"zzz").unwrap();
+        primary.flush().unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        let mut iter = secondary.new_iter().unwrap();
+        let keys: Vec<_> = LdbIteratorIter::wrap(&mut iter)
+            .map(|(k, _)| String::from_utf8(k).unwrap())
+            .collect();
+        assert_eq!(vec!["bbb", "ccc", "ddd", "eee"], keys);
+
+        let mut ro = DB::open_read_only("db", opt).unwrap();
+        assert!(ro.try_catch_up_with_primary().is_err());
+    }
+
+    #[test]
+    fn test_db_impl_secondary_truncated_log_record() {
+        let count = Rc::new(Cell::new(0));
+        let mut opt = options::for_test();
+        opt.paranoid_checks = true;
+        opt.corruption_reporter = Some(share(Box::new(CountingReporter(count.clone()))));
+        let mut primary = DB::open("db", opt.clone()).unwrap();
+        primary.put(b"aaa", b"1").unwrap();
+        primary.flush().unwrap();
+        let mut secondary = DB::open_as_secondary("db", opt.clone()).unwrap();
+
+        // Cut off the primary's last record, as if it were still being written.
+        primary.put(b"bbb", b"2").unwrap();
+        primary.flush().unwrap();
+        let log = log_file_name(Path::new("db"), primary.log_num.unwrap());
+        let mut contents = vec![];
+        opt.env
+            .open_sequential_file(&log)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        let write_log = |contents: &[u8]| {
+            opt.env
+                .open_writable_file(&log)
+                .unwrap()
+                .write_all(contents)
+                .unwrap()
+        };
+        write_log(&contents[..contents.len() - 3]);
+
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(Some(b"1".to_vec()), secondary.get(b"aaa"));
+        assert_eq!(None, secondary.get(b"bbb"));
+        assert_eq!(0, count.get());
+
+        // Once the record is complete, it's read.
+        write_log(&contents);
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(Some(b"2".to_vec()), secondary.get(b"bbb"));
+        assert_eq!(0, count.get());
+    }
 }
@@ -1,7 +1,9 @@
 use crate::cmp::Cmp;
+use crate::error::{Result, Status};
 use crate::key_types::{parse_internal_key, truncate_to_userkey, LookupKey, ValueType};
 use crate::merging_iter::MergingIter;
 use crate::snapshot::Snapshot;
+use crate::statistics::{self, Histogram};
 use crate::types::{Direction, LdbIterator, Shared};
 use crate::version_set::VersionSet;
 
@@ -24,6 +26,9 @@ pub struct DBIterator {
     ss: Snapshot,
     dir: Direction,
     byte_count: isize,
+    // With paranoid_checks, the database's sticky error, set when the iterator is stopped by an
+    // error.
+    bg_error: Option<Shared<Option<Status>>>,
 
     valid: bool,
     // temporarily stored user key.
@@ -40,6 +45,7 @@ impl DBIterator {
         vset: Shared<VersionSet>,
         iter: MergingIter,
         ss: Snapshot,
+        bg_error: Option<Shared<Option<Status>>>,
     ) -> DBIterator {
         DBIterator {
             cmp,
@@ -48,6 +54,7 @@ impl DBIterator {
             ss,
             dir: Direction::Forward,
             byte_count: random_period(),
+            bg_error,
 
             valid: false,
             savedkey: vec![],
@@ -70,6 +77,17 @@ impl DBIterator {
         }
     }
 
+    /// record_error puts the database into an error state if the iterator has become invalid
+    /// because of an error.
+    fn record_error(&mut self) {
+        if let (Some(bg_error), Err(e)) = (&self.bg_error, self.iter.status()) {
+            let mut bg_error = bg_error.borrow_mut();
+            if bg_error.is_none() {
+                *bg_error = Some(e);
+            }
+        }
+    }
+
     /// find_next_user_entry skips to the next user entry after the one saved in self.savedkey.
     fn find_next_user_entry(&mut self, mut skipping: bool) -> bool {
         assert!(self.iter.valid());
@@ -102,6 +120,7 @@ impl DBIterator {
         }
         self.savedkey.clear();
         self.valid = false;
+        self.record_error();
         false
     }
 
@@ -152,6 +171,7 @@ impl DBIterator {
 
         if value_type == ValueType::TypeDeletion {
             self.valid = false;
+            self.record_error();
             self.savedkey.clear();
             self.savedval.clear();
             self.dir = Direction::Forward;
@@ -178,6 +198,7 @@ impl LdbIterator for DBIterator {
             }
             if !self.iter.valid() {
                 self.valid = false;
+                self.record_error();
                 self.savedkey.clear();
                 return false;
             }
@@ -224,6 +245,7 @@ impl LdbIterator for DBIterator {
                 self.iter.prev();
                 if !self.iter.valid() {
                     self.valid = false;
+                    self.record_error();
                     self.savedkey.clear();
                     self.savedval.clear();
                     return false;
@@ -243,6 +265,7 @@ impl LdbIterator for DBIterator {
         self.valid
     }
     fn seek(&mut self, to: &[u8]) {
+        let start = statistics::start_timer(self.vset.borrow().options());
         self.dir = Direction::Forward;
         self.savedkey.clear();
         self.savedval.clear();
@@ -256,7 +279,9 @@ impl LdbIterator for DBIterator {
             );
         } else {
             self.valid = false;
+            self.record_error();
         }
+        statistics::record_time(self.vset.borrow().options(), Histogram::Seek, start);
     }
     fn seek_to_first(&mut self) {
         self.dir = Direction::Forward;
@@ -269,6 +294,7 @@ impl LdbIterator for DBIterator {
             );
         } else {
             self.valid = false;
+            self.record_error();
         }
     }
     fn reset(&mut self) {
@@ -278,6 +304,9 @@ impl LdbIterator for DBIterator {
         self.savedval.clear();
         self.keybuf.clear();
     }
+    fn status(&self) -> Result<()> {
+        self.iter.status()
+    }
 }
 
 fn random_period() -> isize {
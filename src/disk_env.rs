@@ -1,7 +1,10 @@
+#[cfg(target_os = "linux")]
+use crate::direct_io;
 use crate::env::{path_to_str, Env, FileLock, Logger, RandomAccess};
 use crate::env_common::{micros, sleep_for};
 use crate::error::{err, Result, Status, StatusCode};
 use fs2::FileExt;
+use memmap2::Mmap;
 
 use std::collections::HashMap;
 use std::fs::{self, File};
@@ -9,6 +12,7 @@ use std::io::{self, ErrorKind, Read, Write};
 use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
 
 type FileDescriptor = i32;
 
@@ -32,6 +36,22 @@ fn map_err_with_name(method: &'static str, f: &Path, e: io::Error) -> Status {
     s
 }
 
+/// MmapFile serves reads from a read-only memory mapping of a file, without system calls.
+struct MmapFile {
+    map: Mmap,
+}
+
+impl RandomAccess for MmapFile {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        if off >= self.map.len() {
+            return Ok(0);
+        }
+        let n = dst.len().min(self.map.len() - off);
+        dst[..n].copy_from_slice(&self.map[off..off + n]);
+        Ok(n)
+    }
+}
+
 // Note: We're using Ok(f()?) in several locations below in order to benefit from the automatic
 // error conversion using std::convert::From.
 impl Env for PosixDiskEnv {
@@ -53,6 +73,35 @@ impl Env for PosixDiskEnv {
             })
             .map_err(|e| map_err_with_name("open (randomaccess)", p, e))?)
     }
+    fn open_mmap_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        let f = fs::OpenOptions::new()
+            .read(true)
+            .open(p)
+            .map_err(|e| map_err_with_name("open (mmap)", p, e))?;
+        // Safe as long as the file isn't truncated while mapped, which doesn't happen to tables.
+        let map = unsafe { Mmap::map(&f) }.map_err(|e| map_err_with_name("mmap", p, e))?;
+        Ok(Box::new(MmapFile { map }))
+    }
+    #[cfg(target_os = "linux")]
+    fn open_direct_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        let opts = fs::OpenOptions::new().read(true).clone();
+        match direct_io::open(p, &opts).map_err(|e| map_err_with_name("open (direct)", p, e))? {
+            Some(f) => Ok(Box::new(direct_io::DirectFile::new(f))),
+            None => self.open_random_access_file(p),
+        }
+    }
+    #[cfg(target_os = "linux")]
+    fn open_direct_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        let opts = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .clone();
+        match direct_io::open(p, &opts).map_err(|e| map_err_with_name("open (direct)", p, e))? {
+            Some(f) => Ok(Box::new(direct_io::DirectWriter::new(f))),
+            None => self.open_writable_file(p),
+        }
+    }
     fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
         Ok(Box::new(
             fs::OpenOptions::new()
@@ -94,6 +143,13 @@ impl Env for PosixDiskEnv {
         let meta = fs::metadata(p).map_err(|e| map_err_with_name("size_of", p, e))?;
         Ok(meta.len() as usize)
     }
+    fn modified_micros(&self, p: &Path) -> Result<u64> {
+        let modified = fs::metadata(p)
+            .and_then(|meta| meta.modified())
+            .map_err(|e| map_err_with_name("modified_micros", p, e))?;
+        let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(since_epoch.as_micros() as u64)
+    }
 
     fn delete(&self, p: &Path) -> Result<()> {
         Ok(fs::remove_file(p).map_err(|e| map_err_with_name("delete", p, e))?)
@@ -107,6 +163,9 @@ impl Env for PosixDiskEnv {
     fn rename(&self, old: &Path, new: &Path) -> Result<()> {
         Ok(fs::rename(old, new).map_err(|e| map_err_with_name("rename", old, e))?)
     }
+    fn link(&self, old: &Path, new: &Path) -> Result<()> {
+        fs::hard_link(old, new).map_err(|e| map_err_with_name("link", old, e))
+    }
 
     fn lock(&self, p: &Path) -> Result<FileLock> {
         let mut locks = self.locks.lock().unwrap();
@@ -211,11 +270,26 @@ mod tests {
             assert!(!env.exists(name).unwrap());
             // rename back so that the remaining tests can use the file.
             assert!(env.rename(newname, name).is_ok());
+
+            // link
+            assert!(env.link(name, newname).is_ok());
+            assert_eq!(6, env.size_of(newname).unwrap());
+            assert!(env.delete(newname).is_ok());
+            assert!(env.exists(name).unwrap());
         }
 
         assert!(env.open_sequential_file(name).is_ok());
         assert!(env.open_random_access_file(name).is_ok());
 
+        // mmap
+        {
+            let f = env.open_mmap_file(name).unwrap();
+            let mut buf = [0; 4];
+            assert_eq!(3, f.read_at(3, &mut buf).unwrap());
+            assert_eq!(b"xyz", &buf[..3]);
+            assert_eq!(0, f.read_at(6, &mut buf).unwrap());
+        }
+
         assert!(env.delete(name).is_ok());
     }
 
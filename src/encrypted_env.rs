@@ -0,0 +1,473 @@
+//! encrypted_env contains an Env encrypting the contents of all files of another Env using
+//! ChaCha20.
+//!
+//! Every file starts with a header containing the ID of the key and the random nonce it is
+//! encrypted with; the following contents are XORed with the key stream for that nonce, which
+//! allows decrypting any part of a file independently.
+
+use crate::env::{Env, FileLock, Logger, RandomAccess};
+use crate::error::{err, Result, Status, StatusCode};
+
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::ChaCha20;
+use integer_encoding::FixedInt;
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub const KEY_LEN: usize = 32;
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 12;
+/// The length of the header preceding the encrypted contents of a file.
+const HEADER_LEN: usize = KEY_ID_LEN + NONCE_LEN;
+/// The length of the key stream for a nonce, limited by the 32 bit block counter of ChaCha20. The
+/// last counter value isn't used.
+const MAX_CONTENTS_LEN: u64 = 64 * u32::MAX as u64;
+
+/// A KeyProvider supplies the keys used by an EncryptedEnv to encrypt and decrypt files, for
+/// example after fetching them from a key management service. Every key has an ID, which is
+/// stored in the files encrypted with it; this allows rotating keys while older files still use
+/// the previous ones.
+pub trait KeyProvider {
+    /// Returns the ID of the key that new files are encrypted with, and the key itself.
+    fn current_key(&self) -> Result<(u32, [u8; KEY_LEN])>;
+    /// Returns the key with the given ID.
+    fn key(&self, id: u32) -> Result<[u8; KEY_LEN]>;
+}
+
+/// StaticKeyProvider provides a single, fixed key with the ID 0.
+pub struct StaticKeyProvider {
+    key: [u8; KEY_LEN],
+}
+
+impl StaticKeyProvider {
+    pub fn new(key: [u8; KEY_LEN]) -> StaticKeyProvider {
+        StaticKeyProvider { key }
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn current_key(&self) -> Result<(u32, [u8; KEY_LEN])> {
+        Ok((0, self.key))
+    }
+    fn key(&self, id: u32) -> Result<[u8; KEY_LEN]> {
+        if id != 0 {
+            return err(
+                StatusCode::NotFound,
+                &format!("unknown encryption key: {}", id),
+            );
+        }
+        Ok(self.key)
+    }
+}
+
+/// Cipher is the key and nonce that a file is encrypted with.
+struct Cipher {
+    key: [u8; KEY_LEN],
+    nonce: [u8; NONCE_LEN],
+}
+
+impl Cipher {
+    /// XOR `data` with the key stream starting at byte `offset` of it. This both encrypts and
+    /// decrypts.
+    fn apply_keystream(&self, offset: u64, data: &mut [u8]) -> Result<()> {
+        if offset + data.len() as u64 > MAX_CONTENTS_LEN {
+            return err(
+                StatusCode::InvalidArgument,
+                "encrypted files can't be larger than 256 GiB - 64 B",
+            );
+        }
+        let mut cipher = ChaCha20::new(&self.key.into(), &self.nonce.into());
+        cipher
+            .try_seek(offset)
+            .and_then(|_| cipher.try_apply_keystream(data))
+            .or_else(|e| err(StatusCode::InvalidArgument, &e.to_string()))
+    }
+}
+
+fn to_io_error(s: Status) -> io::Error {
+    io::Error::other(s)
+}
+
+/// EncryptedFile decrypts reads from an encrypted file.
+struct EncryptedFile {
+    f: Box<dyn RandomAccess>,
+    cipher: Cipher,
+}
+
+impl RandomAccess for EncryptedFile {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        let n = self.f.read_at(HEADER_LEN + off, dst)?;
+        self.cipher.apply_keystream(off as u64, &mut dst[..n])?;
+        Ok(n)
+    }
+    fn read_batch(&self, reqs: &mut [(usize, &mut [u8])]) -> Result<Vec<usize>> {
+        let mut encrypted: Vec<(usize, &mut [u8])> = reqs
+            .iter_mut()
+            .map(|(off, dst)| (HEADER_LEN + *off, &mut **dst))
+            .collect();
+        let lens = self.f.read_batch(&mut encrypted)?;
+        for ((off, dst), n) in reqs.iter_mut().zip(&lens) {
+            self.cipher.apply_keystream(*off as u64, &mut dst[..*n])?;
+        }
+        Ok(lens)
+    }
+}
+
+/// EncryptedReader decrypts an encrypted file read sequentially.
+struct EncryptedReader {
+    r: Box<dyn Read>,
+    cipher: Cipher,
+    offset: u64,
+}
+
+impl Read for EncryptedReader {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let n = self.r.read(dst)?;
+        self.cipher
+            .apply_keystream(self.offset, &mut dst[..n])
+            .map_err(to_io_error)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// EncryptedWriter encrypts everything written to a file.
+struct EncryptedWriter {
+    w: Box<dyn Write>,
+    cipher: Cipher,
+    offset: u64,
+}
+
+impl Write for EncryptedWriter {
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        let mut buf = src.to_vec();
+        self.cipher
+            .apply_keystream(self.offset, &mut buf)
+            .map_err(to_io_error)?;
+        // A partial write would leave the offset ambiguous.
+        self.w.write_all(&buf)?;
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// EncryptedEnv wraps another Env, e.g. PosixDiskEnv or MemEnv, and encrypts all files written
+/// through it -- tables, logs, manifests and info logs -- with a key supplied by a KeyProvider.
+/// File names and sizes are not hidden. Writes that would make a file exceed 256 GiB - 64 B fail.
+///
+/// A database written using an EncryptedEnv can only be opened using an EncryptedEnv providing
+/// the same keys.
+pub struct EncryptedEnv {
+    env: Rc<Box<dyn Env>>,
+    keys: Box<dyn KeyProvider>,
+}
+
+impl EncryptedEnv {
+    pub fn new(env: Rc<Box<dyn Env>>, keys: Box<dyn KeyProvider>) -> EncryptedEnv {
+        EncryptedEnv { env, keys }
+    }
+
+    /// Returns the cipher for a file with the given header.
+    fn cipher(&self, header: &[u8; HEADER_LEN]) -> Result<Cipher> {
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&header[KEY_ID_LEN..]);
+        Ok(Cipher {
+            key: self.keys.key(u32::decode_fixed(&header[..KEY_ID_LEN]))?,
+            nonce,
+        })
+    }
+
+    fn read_header(&self, f: &dyn RandomAccess, p: &Path) -> Result<Cipher> {
+        let mut header = [0; HEADER_LEN];
+        if f.read_at(0, &mut header)? < HEADER_LEN {
+            return err(
+                StatusCode::Corruption,
+                &format!("missing encryption header: {}", p.display()),
+            );
+        }
+        self.cipher(&header)
+    }
+
+    /// Starts a new encrypted file by writing its header to `w`.
+    fn new_writer(&self, mut w: Box<dyn Write>) -> Result<Box<dyn Write>> {
+        let (id, key) = self.keys.current_key()?;
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let mut header = [0; HEADER_LEN];
+        id.encode_fixed(&mut header[..KEY_ID_LEN]);
+        header[KEY_ID_LEN..].copy_from_slice(&nonce);
+        w.write_all(&header)?;
+        Ok(Box::new(EncryptedWriter {
+            w,
+            cipher: Cipher { key, nonce },
+            offset: 0,
+        }))
+    }
+}
+
+impl Env for EncryptedEnv {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+        let mut r = self.env.open_sequential_file(p)?;
+        let mut header = [0; HEADER_LEN];
+        if let Err(e) = r.read_exact(&mut header) {
+            if e.kind() != io::ErrorKind::UnexpectedEof {
+                return Err(e.into());
+            }
+            return err(
+                StatusCode::Corruption,
+                &format!("missing encryption header: {}", p.display()),
+            );
+        }
+        Ok(Box::new(EncryptedReader {
+            r,
+            cipher: self.cipher(&header)?,
+            offset: 0,
+        }))
+    }
+    fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        let f = self.env.open_random_access_file(p)?;
+        let cipher = self.read_header(f.as_ref(), p)?;
+        Ok(Box::new(EncryptedFile { f, cipher }))
+    }
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        self.new_writer(self.env.open_writable_file(p)?)
+    }
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        let size = if self.env.exists(p)? {
+            self.env.size_of(p)?
+        } else {
+            0
+        };
+        let w = self.env.open_appendable_file(p)?;
+        if size == 0 {
+            return self.new_writer(w);
+        }
+        // Continue the key stream of the existing file.
+        let cipher = self.read_header(self.env.open_random_access_file(p)?.as_ref(), p)?;
+        Ok(Box::new(EncryptedWriter {
+            w,
+            cipher,
+            offset: size.saturating_sub(HEADER_LEN) as u64,
+        }))
+    }
+
+    fn exists(&self, p: &Path) -> Result<bool> {
+        self.env.exists(p)
+    }
+    fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        self.env.children(p)
+    }
+    fn size_of(&self, p: &Path) -> Result<usize> {
+        Ok(self.env.size_of(p)?.saturating_sub(HEADER_LEN))
+    }
+    fn modified_micros(&self, p: &Path) -> Result<u64> {
+        self.env.modified_micros(p)
+    }
+
+    fn delete(&self, p: &Path) -> Result<()> {
+        self.env.delete(p)
+    }
+    fn mkdir(&self, p: &Path) -> Result<()> {
+        self.env.mkdir(p)
+    }
+    fn rmdir(&self, p: &Path) -> Result<()> {
+        self.env.rmdir(p)
+    }
+    fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        self.env.rename(old, new)
+    }
+    fn link(&self, old: &Path, new: &Path) -> Result<()> {
+        self.env.link(old, new)
+    }
+
+    fn lock(&self, p: &Path) -> Result<FileLock> {
+        self.env.lock(p)
+    }
+    fn unlock(&self, l: FileLock) -> Result<()> {
+        self.env.unlock(l)
+    }
+
+    fn new_logger(&self, p: &Path) -> Result<Logger> {
+        self.open_appendable_file(p).map(Logger::new)
+    }
+
+    fn micros(&self) -> u64 {
+        self.env.micros()
+    }
+    fn sleep_for(&self, micros: u32) {
+        self.env.sleep_for(micros)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_env::MemEnv;
+    use crate::options;
+    use crate::DB;
+
+    fn new_env() -> (Rc<Box<dyn Env>>, EncryptedEnv) {
+        let mem: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        let keys = Box::new(StaticKeyProvider::new([7; KEY_LEN]));
+        (mem.clone(), EncryptedEnv::new(mem, keys))
+    }
+
+    fn read_raw(env: &dyn Env, p: &Path) -> Vec<u8> {
+        let mut contents = vec![];
+        env.open_sequential_file(p)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_encrypted_env_files() {
+        let (mem, env) = new_env();
+        let p = Path::new("/a/file");
+        let data = b"some secret data".repeat(20);
+
+        env.open_writable_file(p)
+            .unwrap()
+            .write_all(&data[..100])
+            .unwrap();
+        env.open_appendable_file(p)
+            .unwrap()
+            .write_all(&data[100..])
+            .unwrap();
+        assert_eq!(data.len(), env.size_of(p).unwrap());
+
+        let raw = read_raw(mem.as_ref().as_ref(), p);
+        assert_eq!(HEADER_LEN + data.len(), raw.len());
+        assert!(!raw.windows(6).any(|w| w == b"secret"));
+
+        assert_eq!(data, read_raw(&env, p));
+        let f = env.open_random_access_file(p).unwrap();
+        let mut buf = [0; 10];
+        for off in [0, 1, 63, 64, 99, 100, data.len() - 10] {
+            assert_eq!(10, f.read_at(off, &mut buf).unwrap());
+            assert_eq!(&data[off..off + 10], &buf[..]);
+        }
+        let (mut a, mut b) = ([0; 5], [0; 20]);
+        let lens = f
+            .read_batch(&mut [(3, &mut a[..]), (data.len() - 7, &mut b[..])])
+            .unwrap();
+        assert_eq!(vec![5, 7], lens);
+        assert_eq!(&data[3..8], &a[..]);
+        assert_eq!(&data[data.len() - 7..], &b[..7]);
+
+        // A different key yields garbage.
+        let other = EncryptedEnv::new(mem, Box::new(StaticKeyProvider::new([8; KEY_LEN])));
+        assert_ne!(data, read_raw(&other, p));
+    }
+
+    #[test]
+    fn test_encrypted_env_max_size() {
+        let cipher = Cipher {
+            key: [7; KEY_LEN],
+            nonce: [1; NONCE_LEN],
+        };
+        let mut buf = [0; 128];
+        cipher.apply_keystream(0, &mut buf).unwrap();
+        cipher
+            .apply_keystream(MAX_CONTENTS_LEN - 128, &mut buf)
+            .unwrap();
+        assert!(cipher
+            .apply_keystream(MAX_CONTENTS_LEN - 127, &mut buf)
+            .is_err());
+        assert!(cipher.apply_keystream(MAX_CONTENTS_LEN, &mut []).is_ok());
+
+        let mut w = EncryptedWriter {
+            w: Box::new(vec![]),
+            cipher,
+            offset: MAX_CONTENTS_LEN - 10,
+        };
+        assert!(w.write(&[0; 11]).is_err());
+        assert_eq!(10, w.write(&[0; 10]).unwrap());
+        assert!(w.write(&[0]).is_err());
+    }
+
+    /// RotatingKeyProvider encrypts new files with the last of its keys.
+    struct RotatingKeyProvider(Rc<std::cell::RefCell<Vec<[u8; KEY_LEN]>>>);
+
+    impl KeyProvider for RotatingKeyProvider {
+        fn current_key(&self) -> Result<(u32, [u8; KEY_LEN])> {
+            let keys = self.0.borrow();
+            Ok((keys.len() as u32 - 1, keys[keys.len() - 1]))
+        }
+        fn key(&self, id: u32) -> Result<[u8; KEY_LEN]> {
+            match self.0.borrow().get(id as usize) {
+                Some(key) => Ok(*key),
+                None => err(StatusCode::NotFound, "unknown key"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encrypted_env_key_rotation() {
+        let mem: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        let keys = Rc::new(std::cell::RefCell::new(vec![[1; KEY_LEN]]));
+        let env = EncryptedEnv::new(mem.clone(), Box::new(RotatingKeyProvider(keys.clone())));
+        let (p1, p2) = (Path::new("/a/1"), Path::new("/a/2"));
+
+        env.open_writable_file(p1)
+            .unwrap()
+            .write_all(b"one")
+            .unwrap();
+        keys.borrow_mut().push([2; KEY_LEN]);
+        env.open_writable_file(p2)
+            .unwrap()
+            .write_all(b"two")
+            .unwrap();
+        // Appending continues with the key the file was created with.
+        env.open_appendable_file(p1)
+            .unwrap()
+            .write_all(b"more")
+            .unwrap();
+
+        assert_eq!(b"onemore".to_vec(), read_raw(&env, p1));
+        assert_eq!(b"two".to_vec(), read_raw(&env, p2));
+        assert_eq!(
+            &[0, 0, 0, 0],
+            &read_raw(mem.as_ref().as_ref(), p1)[..KEY_ID_LEN]
+        );
+        assert_eq!(
+            &[1, 0, 0, 0],
+            &read_raw(mem.as_ref().as_ref(), p2)[..KEY_ID_LEN]
+        );
+
+        // A file encrypted with an unknown key can't be opened.
+        let other = EncryptedEnv::new(mem, Box::new(StaticKeyProvider::new([1; KEY_LEN])));
+        assert_eq!(b"onemore".to_vec(), read_raw(&other, p1));
+        assert!(other.open_sequential_file(p2).is_err());
+        assert!(other.open_random_access_file(p2).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_env_db() {
+        let (mem, env) = new_env();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(env));
+
+        {
+            let mut db = DB::open("/db", opt.clone()).unwrap();
+            db.put(b"secretkey", b"secretvalue").unwrap();
+            db.flush().unwrap();
+            db.compact_range(b"a", b"z").unwrap();
+            db.put(b"otherkey", b"othervalue").unwrap();
+        }
+
+        let mut db = DB::open("/db", opt).unwrap();
+        assert_eq!(b"secretvalue".to_vec(), db.get(b"secretkey").unwrap());
+        assert_eq!(b"othervalue".to_vec(), db.get(b"otherkey").unwrap());
+
+        for name in mem.children(Path::new("/db")).unwrap() {
+            let raw = read_raw(mem.as_ref().as_ref(), &Path::new("/db").join(name));
+            assert!(!raw.windows(6).any(|w| w == b"secret"));
+        }
+    }
+}
@@ -1,7 +1,7 @@
 //! An `env` is an abstraction layer that allows the database to run both on different platforms as
 //! well as persisting data on disk or in memory.
 
-use crate::error::Result;
+use crate::error::{err, Result, StatusCode};
 
 use std::fs::File;
 use std::io::prelude::*;
@@ -13,6 +13,14 @@ use std::path::{Path, PathBuf};
 
 pub trait RandomAccess {
     fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize>;
+    /// Read several ranges, each given by its offset and the buffer to read it into, and return
+    /// the number of bytes read into each buffer. Implementations may submit all reads at once;
+    /// by default, they are read one after another.
+    fn read_batch(&self, reqs: &mut [(usize, &mut [u8])]) -> Result<Vec<usize>> {
+        reqs.iter_mut()
+            .map(|(off, dst)| self.read_at(*off, dst))
+            .collect()
+    }
 }
 
 #[cfg(unix)]
@@ -36,17 +44,49 @@ pub struct FileLock {
 pub trait Env {
     fn open_sequential_file(&self, _: &Path) -> Result<Box<dyn Read>>;
     fn open_random_access_file(&self, _: &Path) -> Result<Box<dyn RandomAccess>>;
+    /// Open a file for random reads served from a memory mapping of it. The file must not be
+    /// modified while it is open. Envs not supporting this open it using
+    /// `open_random_access_file()`.
+    fn open_mmap_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        self.open_random_access_file(p)
+    }
+    /// Open a file for random reads bypassing the operating system's page cache. Envs not
+    /// supporting this open it using `open_random_access_file()`.
+    fn open_direct_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        self.open_random_access_file(p)
+    }
+    /// Open a new file for writes bypassing the operating system's page cache. Envs not
+    /// supporting this open it using `open_writable_file()`.
+    fn open_direct_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        self.open_writable_file(p)
+    }
     fn open_writable_file(&self, _: &Path) -> Result<Box<dyn Write>>;
     fn open_appendable_file(&self, _: &Path) -> Result<Box<dyn Write>>;
 
     fn exists(&self, _: &Path) -> Result<bool>;
     fn children(&self, _: &Path) -> Result<Vec<PathBuf>>;
     fn size_of(&self, _: &Path) -> Result<usize>;
+    /// Returns the time of a file's last modification, in microseconds since the Unix epoch like
+    /// `micros()`. Envs not supporting this return an error with `StatusCode::NotSupported`.
+    fn modified_micros(&self, p: &Path) -> Result<u64> {
+        err(
+            StatusCode::NotSupported,
+            &format!("modified_micros: {}", path_to_str(p)),
+        )
+    }
 
     fn delete(&self, _: &Path) -> Result<()>;
     fn mkdir(&self, _: &Path) -> Result<()>;
     fn rmdir(&self, _: &Path) -> Result<()>;
     fn rename(&self, _: &Path, _: &Path) -> Result<()>;
+    /// Create `new` as a hard link to the file `old`. Envs not supporting this return an error
+    /// with `StatusCode::NotSupported`.
+    fn link(&self, old: &Path, _new: &Path) -> Result<()> {
+        err(
+            StatusCode::NotSupported,
+            &format!("link: {}", path_to_str(old)),
+        )
+    }
 
     fn lock(&self, _: &Path) -> Result<FileLock>;
     fn unlock(&self, l: FileLock) -> Result<()>;
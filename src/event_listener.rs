@@ -0,0 +1,41 @@
+//! event_listener contains the EventListener trait, through which applications can observe the
+//! background work of a database, e.g. to export it to a monitoring system.
+
+use crate::db_impl::CompactionStats;
+use crate::error::{Result, Status};
+use crate::types::FileNum;
+
+/// EventListener is notified about flushes, compactions and other events that are otherwise only
+/// written to the info log. Listeners are registered in `Options::listeners` and called
+/// synchronously; expensive work should be deferred. All methods do nothing by default.
+pub trait EventListener {
+    /// Called before a memtable is written to table `table`.
+    fn on_flush_begin(&self, _table: FileNum) {}
+    /// Called after a memtable has been written to table `table`, which was placed at `level`. An
+    /// empty memtable results in no table, and `stats.written` being 0.
+    fn on_flush_end(&self, _table: FileNum, _level: usize, _stats: &CompactionStats) {}
+    /// Called before tables from `level` are merged into `output_level`. Trivial moves of a table
+    /// to the next level are not reported.
+    fn on_compaction_begin(&self, _level: usize, _output_level: usize) {}
+    /// Called after a compaction started by `on_compaction_begin()` has finished or failed. On
+    /// success, its output tables are already part of the current version.
+    fn on_compaction_end(
+        &self,
+        _level: usize,
+        _output_level: usize,
+        _stats: &CompactionStats,
+        _result: &Result<()>,
+    ) {
+    }
+    /// Called after the table `table` of `size` bytes has been written by a flush, a compaction
+    /// or an ingestion.
+    fn on_table_file_created(&self, _table: FileNum, _size: usize) {}
+    /// Called after the obsolete table `table` has been deleted.
+    fn on_table_file_deleted(&self, _table: FileNum) {}
+    /// Called when a write had to wait for a full memtable to be flushed and for the resulting
+    /// compactions; `micros` is the time it was blocked.
+    fn on_write_stall(&self, _micros: u64) {}
+    /// Called when a flush or compaction fails, and when the database enters the error state,
+    /// after which writes fail (see `Options::paranoid_checks`).
+    fn on_background_error(&self, _error: &Status) {}
+}
@@ -1,17 +1,327 @@
+//! infolog contains the InfoLogger trait, which receives the messages written with the `log!`
+//! macro, and its implementations.
+
+use crate::env::Env;
+use crate::error::Result;
+use crate::options::Options;
+
+use std::fmt;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-pub struct Logger(pub Box<dyn Write>);
+/// LogLevel is the severity of a message.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+}
 
-pub fn stderr() -> Logger {
-    Logger(Box::new(io::stderr()))
+/// InfoLogger receives human-readable messages about the operation of a database, e.g. about
+/// recovery, flushes and compactions.
+pub trait InfoLogger {
+    fn log(&mut self, level: LogLevel, msg: fmt::Arguments);
 }
 
+/// Write `msg` at `level` to the logger `$l`, an `Option<Shared<Box<dyn InfoLogger>>>`. The level
+/// (`Debug`, `Info`, `Warn` or `Error`) may be omitted and defaults to `Info`.
 #[macro_export]
 macro_rules! log {
-    ($l:expr) => ($l.as_ref().map(|l| l.borrow_mut().0.write("\n".as_bytes()).is_ok()));
-    ($l:expr, $fmt:expr) => (
-        $l.as_ref().map(|l| l.borrow_mut().0.write(concat!($fmt, "\n").as_bytes()).is_ok()));
-    ($l:expr, $fmt:expr, $($arg:tt)*) => (
-        $l.as_ref().map(
-            |l| l.borrow_mut().0.write_fmt(format_args!(concat!($fmt, "\n"), $($arg)*)).is_ok()));
+    (@ $l:expr, $level:ident, $($arg:tt)+) => (
+        $l.as_ref().map(|l| l.borrow_mut().log(
+            $crate::infolog::LogLevel::$level,
+            format_args!($($arg)+),
+        ))
+    );
+    ($l:expr, Debug, $($arg:tt)+) => (log!(@ $l, Debug, $($arg)+));
+    ($l:expr, Info, $($arg:tt)+) => (log!(@ $l, Info, $($arg)+));
+    ($l:expr, Warn, $($arg:tt)+) => (log!(@ $l, Warn, $($arg)+));
+    ($l:expr, Error, $($arg:tt)+) => (log!(@ $l, Error, $($arg)+));
+    ($l:expr, $($arg:tt)+) => (log!(@ $l, Info, $($arg)+));
+}
+
+/// Format a time given in microseconds since the epoch as `YYYY/MM/DD-hh:mm:ss.uuuuuu` (UTC).
+fn format_micros(micros: u64) -> String {
+    let secs = micros / 1_000_000;
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // Convert days since the epoch to a civil date (see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}/{:02}/{:02}-{:02}:{:02}:{:02}.{:06}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+        micros % 1_000_000
+    )
+}
+
+/// WriteLogger writes every message of at least a minimum level as a line, prefixed with a
+/// timestamp and the level.
+pub struct WriteLogger {
+    env: Rc<Box<dyn Env>>,
+    dst: Box<dyn Write>,
+    level: LogLevel,
+    written: usize,
+}
+
+impl WriteLogger {
+    pub fn new(env: Rc<Box<dyn Env>>, dst: Box<dyn Write>, level: LogLevel) -> WriteLogger {
+        WriteLogger {
+            env,
+            dst,
+            level,
+            written: 0,
+        }
+    }
+}
+
+impl InfoLogger for WriteLogger {
+    fn log(&mut self, level: LogLevel, msg: fmt::Arguments) {
+        if level < self.level {
+            return;
+        }
+        let line = format!(
+            "{} {} {}\n",
+            format_micros(self.env.micros()),
+            level.name(),
+            msg
+        );
+        if self.dst.write_all(line.as_bytes()).is_ok() {
+            self.written += line.len();
+        }
+    }
+}
+
+/// Returns a logger writing to stderr.
+pub fn stderr(env: Rc<Box<dyn Env>>) -> WriteLogger {
+    WriteLogger::new(env, Box::new(io::stderr()), LogLevel::Debug)
+}
+
+/// Returns a logger discarding all messages.
+pub fn sink(env: Rc<Box<dyn Env>>) -> WriteLogger {
+    WriteLogger::new(env, Box::new(io::sink()), LogLevel::Error)
+}
+
+const LOG_NAME: &str = "LOG";
+const OLD_LOG_PREFIX: &str = "LOG.old.";
+/// The single old log file kept by earlier versions.
+const LEGACY_OLD_LOG_NAME: &str = "LOG.old";
+
+/// FileLogger writes to the file `LOG` in a database directory. Once the file grows larger than
+/// `Options::max_log_file_size` or older than `Options::log_file_time_to_roll`, it is renamed to
+/// `LOG.old.<micros>` and a new one is started. Only the newest `Options::keep_log_file_num` old
+/// files are kept; a `LOG.old` file left by earlier versions counts as the oldest one.
+pub struct FileLogger {
+    env: Rc<Box<dyn Env>>,
+    dir: PathBuf,
+    inner: WriteLogger,
+    opened: u64,
+    max_size: usize,
+    max_age_micros: u64,
+    keep: usize,
+}
+
+impl FileLogger {
+    /// Open the log file in `dir`, moving an existing one out of the way.
+    pub fn open(opt: &Options, dir: &Path) -> Result<FileLogger> {
+        let env = opt.env.clone();
+        let _ = env.mkdir(dir);
+        let mut logger = FileLogger {
+            inner: sink(env.clone()),
+            env,
+            dir: dir.to_owned(),
+            opened: 0,
+            max_size: opt.max_log_file_size,
+            max_age_micros: opt.log_file_time_to_roll * 1_000_000,
+            keep: opt.keep_log_file_num,
+        };
+        logger.roll(opt.info_log_level)?;
+        Ok(logger)
+    }
+
+    fn roll(&mut self, level: LogLevel) -> Result<()> {
+        let path = self.dir.join(LOG_NAME);
+        let now = self.env.micros();
+        if self.env.exists(&path)? {
+            let old = self.dir.join(format!("{}{}", OLD_LOG_PREFIX, now));
+            self.env.rename(&path, &old)?;
+        }
+        self.purge_old_logs()?;
+        let f = self.env.open_writable_file(&path)?;
+        self.inner = WriteLogger::new(self.env.clone(), f, level);
+        self.opened = now;
+        Ok(())
+    }
+
+    /// Delete the oldest rotated log files beyond the number to keep.
+    fn purge_old_logs(&self) -> Result<()> {
+        let mut old: Vec<(u64, String)> = self
+            .env
+            .children(&self.dir)?
+            .into_iter()
+            .filter_map(|p| {
+                let name = p.to_str()?.to_owned();
+                if name == LEGACY_OLD_LOG_NAME {
+                    return Some((0, name));
+                }
+                let ts = name.strip_prefix(OLD_LOG_PREFIX)?.parse().ok()?;
+                Some((ts, name))
+            })
+            .collect();
+        old.sort();
+        let excess = old.len().saturating_sub(self.keep);
+        for (_, name) in &old[..excess] {
+            self.env.delete(&self.dir.join(name))?;
+        }
+        Ok(())
+    }
+
+    fn should_roll(&self) -> bool {
+        (self.max_size > 0 && self.inner.written >= self.max_size)
+            || (self.max_age_micros > 0
+                && self.env.micros().saturating_sub(self.opened) >= self.max_age_micros)
+    }
+}
+
+impl InfoLogger for FileLogger {
+    fn log(&mut self, level: LogLevel, msg: fmt::Arguments) {
+        if self.should_roll() {
+            let min_level = self.inner.level;
+            // Keep writing to the full file if a new one can't be started.
+            let _ = self.roll(min_level);
+        }
+        self.inner.log(level, msg)
+    }
+}
+
+/// LogCrateLogger passes messages on to the `log` crate, with the target `rusty_leveldb`.
+pub struct LogCrateLogger;
+
+impl InfoLogger for LogCrateLogger {
+    fn log(&mut self, level: LogLevel, msg: fmt::Arguments) {
+        let level = match level {
+            LogLevel::Debug => ::log::Level::Debug,
+            LogLevel::Info => ::log::Level::Info,
+            LogLevel::Warn => ::log::Level::Warn,
+            LogLevel::Error => ::log::Level::Error,
+        };
+        ::log::log!(target: "rusty_leveldb", level, "{}", msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_env::MemEnv;
+    use crate::options;
+    use crate::types::share;
+
+    use std::io::Read;
+
+    #[test]
+    fn test_infolog_format_micros() {
+        assert_eq!("1970/01/01-00:00:00.000000", format_micros(0));
+        assert_eq!(
+            "2000/02/29-23:59:59.000001",
+            format_micros(951_868_799_000_001)
+        );
+        assert_eq!(
+            "2026/10/18-12:34:56.789000",
+            format_micros(1_792_326_896_789_000)
+        );
+    }
+
+    #[test]
+    fn test_infolog_levels() {
+        let opt = options::for_test();
+        let w = opt.env.open_writable_file(Path::new("log")).unwrap();
+        let l: Option<crate::types::Shared<Box<dyn InfoLogger>>> = Some(share(Box::new(
+            WriteLogger::new(opt.env.clone(), w, LogLevel::Info),
+        )));
+        log!(l, Debug, "dropped");
+        log!(l, "plain {}", 1);
+        log!(l, Error, "bad {}", "thing");
+
+        let mut s = String::new();
+        let mut r = opt.env.open_sequential_file(Path::new("log")).unwrap();
+        r.read_to_string(&mut s).unwrap();
+        let lines: Vec<&str> = s.lines().map(|l| &l[27..]).collect();
+        assert_eq!(vec!["INFO plain 1", "ERROR bad thing"], lines);
+    }
+
+    #[test]
+    fn test_infolog_file_logger_rotation() {
+        let env: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        let mut opt = options::for_test();
+        opt.env = env.clone();
+        opt.max_log_file_size = 100;
+        opt.keep_log_file_num = 2;
+        let dir = Path::new("db");
+        let old_logs = || {
+            let mut names: Vec<String> = env
+                .children(dir)
+                .unwrap()
+                .iter()
+                .map(|p| p.to_str().unwrap().to_owned())
+                .filter(|n| n.starts_with(OLD_LOG_PREFIX))
+                .collect();
+            names.sort();
+            names
+        };
+
+        env.mkdir(dir).unwrap();
+        env.open_writable_file(&dir.join(LEGACY_OLD_LOG_NAME))
+            .unwrap()
+            .write_all(b"legacy")
+            .unwrap();
+
+        let mut l = FileLogger::open(&opt, dir).unwrap();
+        assert!(old_logs().is_empty());
+        assert!(env.exists(&dir.join(LEGACY_OLD_LOG_NAME)).unwrap());
+        for i in 0..4 {
+            l.log(LogLevel::Info, format_args!("message {:060}", i));
+            env.sleep_for(1);
+        }
+        // Every message fills up a file. The legacy old log is purged first.
+        assert_eq!(2, old_logs().len());
+        assert!(!env.exists(&dir.join(LEGACY_OLD_LOG_NAME)).unwrap());
+        let mut s = String::new();
+        let mut r = env.open_sequential_file(&dir.join(LOG_NAME)).unwrap();
+        r.read_to_string(&mut s).unwrap();
+        assert!(s.ends_with(&format!(" INFO message {:060}\n", 3)));
+        assert_eq!(1, s.lines().count());
+
+        // An existing log is rotated when opening.
+        drop(l);
+        let _l = FileLogger::open(&opt, dir).unwrap();
+        assert_eq!(2, old_logs().len());
+        assert_eq!(0, env.size_of(&dir.join(LOG_NAME)).unwrap());
+        let newest = old_logs().pop().unwrap();
+        assert!(env.size_of(&dir.join(newest)).unwrap() > 0);
+    }
 }
@@ -44,11 +44,16 @@ mod block;
 mod block_builder;
 mod blockhandle;
 mod cache;
+mod checksum;
 mod cmp;
+#[cfg(target_os = "linux")]
+mod direct_io;
 mod disk_env;
+mod encrypted_env;
 mod env;
 mod env_common;
 mod error;
+mod event_listener;
 mod filter;
 mod filter_block;
 mod key_types;
@@ -57,17 +62,23 @@ mod mem_env;
 mod memtable;
 mod merging_iter;
 mod options;
+mod rate_limiter;
 mod skipmap;
 mod snapshot;
+mod sst_file_writer;
+mod statistics;
 mod table_block;
 mod table_builder;
 mod table_cache;
 mod table_reader;
 mod test_util;
 mod types;
+#[cfg(target_os = "linux")]
+mod uring_env;
 mod version;
 mod version_edit;
 mod version_set;
+mod wal_iter;
 mod write_batch;
 
 mod db_impl;
@@ -76,15 +87,29 @@ mod db_iter;
 #[cfg(feature = "async")]
 pub use asyncdb::AsyncDB;
 
+pub use cache::EvictionPolicy;
 pub use cmp::{Cmp, DefaultCmp};
-pub use db_impl::DB;
+pub use db_impl::{CompactionStats, DB};
 pub use db_iter::DBIterator;
 pub use disk_env::PosixDiskEnv;
+pub use encrypted_env::{EncryptedEnv, KeyProvider, StaticKeyProvider};
 pub use env::Env;
 pub use error::{Result, Status, StatusCode};
+pub use event_listener::EventListener;
 pub use filter::{BloomPolicy, FilterPolicy};
+pub use infolog::{FileLogger, InfoLogger, LogCrateLogger, LogLevel, WriteLogger};
+pub use log::CorruptionReporter;
 pub use mem_env::MemEnv;
-pub use options::{in_memory, CompressionType, Options};
+pub use options::{
+    in_memory, ChecksumType, CompactionStyle, CompressionType, Options, WalRecoveryMode,
+    WriteOptions,
+};
+pub use rate_limiter::{IoSource, RateLimiter};
 pub use skipmap::SkipMap;
+pub use sst_file_writer::SstFileWriter;
+pub use statistics::{Histogram, HistogramData, Statistics, Ticker};
 pub use types::LdbIterator;
+#[cfg(target_os = "linux")]
+pub use uring_env::UringEnv;
+pub use wal_iter::WalIterator;
 pub use write_batch::WriteBatch;
@@ -3,12 +3,12 @@
 //! A record is a bytestring: [checksum: uint32, length: uint16, type: uint8, data: [u8]]
 //! checksum is the crc32 sum of type and data; type is one of RecordType::{Full/First/Middle/Last}
 
-use crate::error::{err, Result, StatusCode};
+use crate::checksum::{crc32c, crc32c_extend};
+use crate::error::{Result, Status, StatusCode};
 
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::ops::Range;
 
-use crc::crc32;
-use crc::Hasher32;
 use integer_encoding::FixedInt;
 use integer_encoding::FixedIntWriter;
 
@@ -25,19 +25,16 @@ pub enum RecordType {
 
 pub struct LogWriter<W: Write> {
     dst: W,
-    digest: crc32::Digest,
     current_block_offset: usize,
     block_size: usize,
 }
 
 impl<W: Write> LogWriter<W> {
     pub fn new(writer: W) -> LogWriter<W> {
-        let digest = crc32::Digest::new(crc32::CASTAGNOLI);
         LogWriter {
             dst: writer,
             current_block_offset: 0,
             block_size: BLOCK_SIZE,
-            digest,
         }
     }
 
@@ -94,11 +91,7 @@ impl<W: Write> LogWriter<W> {
     fn emit_record(&mut self, t: RecordType, data: &[u8], len: usize) -> Result<usize> {
         assert!(len < 256 * 256);
 
-        self.digest.reset();
-        self.digest.write(&[t as u8]);
-        self.digest.write(&data[0..len]);
-
-        let chksum = mask_crc(self.digest.sum32());
+        let chksum = mask_crc(crc32c_extend(crc32c(&[t as u8]), &data[0..len]));
 
         let mut s = 0;
         s += self.dst.write(&chksum.encode_fixed_vec())?;
@@ -116,89 +109,210 @@ impl<W: Write> LogWriter<W> {
     }
 }
 
+/// CorruptionReporter is notified by a LogReader about corrupted data that it skips.
+pub trait CorruptionReporter {
+    /// `bytes` is the approximate number of bytes dropped because of the corruption described by
+    /// `reason`.
+    fn corruption(&mut self, bytes: usize, reason: &Status);
+}
+
 pub struct LogReader<R: Read> {
-    // TODO: Wrap src in a buffer to enhance read performance.
     src: R,
-    digest: crc32::Digest,
-    blk_off: usize,
     blocksize: usize,
-    head_scratch: [u8; 7],
     checksums: bool,
+    reporter: Option<Box<dyn CorruptionReporter>>,
+
+    // The current block and the offset of the next record in it.
+    block: Vec<u8>,
+    blk_off: usize,
+    eof: bool,
+    // Whether a record cut off at the end of the file is reported.
+    report_truncated_tail: bool,
+}
+
+/// A physical record as found in a block, with its type and the location of its payload.
+enum PhysicalRecord {
+    Record(u8, Range<usize>),
+    // Corrupted data has been skipped.
+    Bad,
+    Eof,
 }
 
 impl<R: Read> LogReader<R> {
     pub fn new(src: R, chksum: bool) -> LogReader<R> {
         LogReader {
             src,
-            blk_off: 0,
             blocksize: BLOCK_SIZE,
             checksums: chksum,
-            head_scratch: [0; 7],
-            digest: crc32::Digest::new(crc32::CASTAGNOLI),
+            reporter: None,
+            block: Vec::new(),
+            blk_off: 0,
+            eof: false,
+            report_truncated_tail: true,
         }
     }
 
+    /// Treat a record cut off at the end of the file like the end of the file, without reporting
+    /// it. This is for logs that are still being written: The record can be read once the
+    /// writer has finished it.
+    pub fn ignore_truncated_tail(&mut self) {
+        self.report_truncated_tail = false;
+    }
+
+    /// Set a reporter to be notified of corrupted data. Without a reporter, corruptions are
+    /// returned as errors from read(); in both cases, reading continues with the next block.
+    pub fn set_reporter(&mut self, reporter: Box<dyn CorruptionReporter>) {
+        self.reporter = Some(reporter);
+    }
+
     /// EOF is signalled by Ok(0)
     pub fn read(&mut self, dst: &mut Vec<u8>) -> Result<usize> {
-        let mut checksum: u32;
-        let mut length: u16;
-        let mut typ: u8;
-        let mut dst_offset: usize = 0;
-
+        let mut in_fragmented_record = false;
         dst.clear();
 
         loop {
-            if self.blocksize - self.blk_off < HEADER_SIZE {
-                // skip to next block
-                self.src
-                    .read_exact(&mut self.head_scratch[0..self.blocksize - self.blk_off])?;
-                self.blk_off = 0;
+            let (typ, payload) = match self.read_physical_record()? {
+                // A fragmented record cut off at the end of the file was presumably being written
+                // when the writer stopped. This is not reported.
+                PhysicalRecord::Eof => return Ok(0),
+                PhysicalRecord::Bad => {
+                    if in_fragmented_record {
+                        in_fragmented_record = false;
+                        self.report(dst.len(), "error in middle of record")?;
+                        dst.clear();
+                    }
+                    continue;
+                }
+                PhysicalRecord::Record(typ, payload) => (typ, payload),
+            };
+
+            if typ == RecordType::Full as u8 {
+                if in_fragmented_record {
+                    self.report(dst.len(), "partial record without end")?;
+                }
+                dst.clear();
+                dst.extend_from_slice(&self.block[payload]);
+                return Ok(dst.len());
+            } else if typ == RecordType::First as u8 {
+                if in_fragmented_record {
+                    self.report(dst.len(), "partial record without end")?;
+                }
+                dst.clear();
+                dst.extend_from_slice(&self.block[payload]);
+                in_fragmented_record = true;
+            } else if typ == RecordType::Middle as u8 {
+                if in_fragmented_record {
+                    dst.extend_from_slice(&self.block[payload]);
+                } else {
+                    self.report(payload.len(), "missing start of fragmented record")?;
+                }
+            } else if typ == RecordType::Last as u8 {
+                if in_fragmented_record {
+                    dst.extend_from_slice(&self.block[payload]);
+                    return Ok(dst.len());
+                } else {
+                    self.report(payload.len(), "missing start of fragmented record")?;
+                }
+            } else {
+                let dropped = payload.len() + HEADER_SIZE + dst.len();
+                in_fragmented_record = false;
+                dst.clear();
+                self.report(dropped, &format!("unknown record type {}", typ))?;
             }
+        }
+    }
 
-            let mut bytes_read = self.src.read(&mut self.head_scratch)?;
+    /// read_physical_record returns the next intact record. Corrupted data is skipped up to the
+    /// end of the current block, and reported.
+    fn read_physical_record(&mut self) -> Result<PhysicalRecord> {
+        loop {
+            let remaining = self.block.len() - self.blk_off;
+            if remaining < HEADER_SIZE {
+                if !self.eof {
+                    // Skip the trailer and read the next block.
+                    self.read_block()?;
+                    continue;
+                }
+                self.blk_off = self.block.len();
+                if remaining > 0 && self.report_truncated_tail {
+                    self.report(remaining, "truncated record at end of file")?;
+                }
+                return Ok(PhysicalRecord::Eof);
+            }
 
-            // EOF
-            if bytes_read == 0 {
-                return Ok(0);
+            let header = &self.block[self.blk_off..self.blk_off + HEADER_SIZE];
+            let checksum = u32::decode_fixed(&header[0..4]);
+            let length = u16::decode_fixed(&header[4..6]) as usize;
+            let typ = header[6];
+
+            if length > remaining - HEADER_SIZE {
+                self.blk_off = self.block.len();
+                if self.eof {
+                    if self.report_truncated_tail {
+                        self.report(remaining, "truncated record at end of file")?;
+                    }
+                    return Ok(PhysicalRecord::Eof);
+                }
+                self.report(remaining, "bad record length")?;
+                return Ok(PhysicalRecord::Bad);
             }
 
-            self.blk_off += bytes_read;
+            if typ == 0 && length == 0 {
+                // Zeroed space at the end of a block, e.g. from preallocation.
+                self.blk_off = self.block.len();
+                continue;
+            }
 
-            checksum = u32::decode_fixed(&self.head_scratch[0..4]);
-            length = u16::decode_fixed(&self.head_scratch[4..6]);
-            typ = self.head_scratch[6];
+            let start = self.blk_off + HEADER_SIZE;
+            if self.checksums && !self.check_integrity(typ, start..start + length, checksum) {
+                // The length may be corrupted as well, so drop the rest of the block.
+                self.blk_off = self.block.len();
+                self.report(remaining, "Invalid Checksum")?;
+                return Ok(PhysicalRecord::Bad);
+            }
 
-            dst.resize(dst_offset + length as usize, 0);
-            bytes_read = self
-                .src
-                .read(&mut dst[dst_offset..dst_offset + length as usize])?;
-            self.blk_off += bytes_read;
+            self.blk_off = start + length;
+            return Ok(PhysicalRecord::Record(typ, start..start + length));
+        }
+    }
 
-            if self.checksums
-                && !self.check_integrity(typ, &dst[dst_offset..dst_offset + bytes_read], checksum)
-            {
-                return err(StatusCode::Corruption, "Invalid Checksum");
+    /// read_block reads the next block from the source. A short block marks the end of the file.
+    fn read_block(&mut self) -> Result<()> {
+        self.block.resize(self.blocksize, 0);
+        self.blk_off = 0;
+        let mut filled = 0;
+        while filled < self.blocksize {
+            match self.src.read(&mut self.block[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.block.truncate(filled);
+                    return Err(e.into());
+                }
             }
+        }
+        self.block.truncate(filled);
+        if filled < self.blocksize {
+            self.eof = true;
+        }
+        Ok(())
+    }
 
-            dst_offset += length as usize;
-
-            if typ == RecordType::Full as u8 {
-                return Ok(dst_offset);
-            } else if typ == RecordType::First as u8 {
-                continue;
-            } else if typ == RecordType::Middle as u8 {
-                continue;
-            } else if typ == RecordType::Last as u8 {
-                return Ok(dst_offset);
+    /// report passes a corruption to the reporter or, if there is none, returns it as error.
+    fn report(&mut self, bytes: usize, reason: &str) -> Result<()> {
+        let status = Status::new(StatusCode::Corruption, reason);
+        match self.reporter {
+            Some(ref mut r) => {
+                r.corruption(bytes, &status);
+                Ok(())
             }
+            None => Err(status),
         }
     }
 
-    fn check_integrity(&mut self, typ: u8, data: &[u8], expected: u32) -> bool {
-        self.digest.reset();
-        self.digest.write(&[typ]);
-        self.digest.write(data);
-        unmask_crc(expected) == self.digest.sum32()
+    fn check_integrity(&self, typ: u8, data: Range<usize>, expected: u32) -> bool {
+        unmask_crc(expected) == crc32c_extend(crc32c(&[typ]), &self.block[data])
     }
 }
 
@@ -216,6 +330,10 @@ pub fn unmask_crc(mc: u32) -> u32 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::err;
+    use crate::types::{share, Shared};
+
+    use crc::crc32;
     use std::io::Cursor;
 
     #[test]
@@ -324,4 +442,96 @@ mod tests {
         }
         assert_eq!(i, data.len());
     }
+
+    struct TestReporter(Shared<Vec<(usize, Status)>>);
+
+    impl CorruptionReporter for TestReporter {
+        fn corruption(&mut self, bytes: usize, reason: &Status) {
+            self.0.borrow_mut().push((bytes, reason.clone()));
+        }
+    }
+
+    fn read_all<R: Read>(lr: &mut LogReader<R>) -> Vec<Vec<u8>> {
+        let mut records = vec![];
+        let mut dst = vec![];
+        while lr.read(&mut dst).unwrap() > 0 {
+            records.push(dst.clone());
+        }
+        records
+    }
+
+    #[test]
+    fn test_reader_resync() {
+        let data = [
+            "abcdefghi".as_bytes().to_vec(),
+            "0101010101010101010101".as_bytes().to_vec(), // spans three blocks of 17
+            "123".as_bytes().to_vec(),
+            "456".as_bytes().to_vec(),
+        ];
+        let mut lw = LogWriter::new(Vec::new());
+        lw.block_size = super::HEADER_SIZE + 10;
+        for e in data.iter() {
+            assert!(lw.add_record(e).is_ok());
+        }
+        // Corrupt the middle fragment of the second record.
+        lw.dst[17 + 17 + HEADER_SIZE] += 1;
+
+        let reports = share(vec![]);
+        let mut lr = LogReader::new(lw.dst.as_slice(), true);
+        lr.blocksize = super::HEADER_SIZE + 10;
+        lr.set_reporter(Box::new(TestReporter(reports.clone())));
+
+        assert_eq!(
+            vec![data[0].clone(), data[2].clone(), data[3].clone()],
+            read_all(&mut lr)
+        );
+        // The first and last fragments are dropped as well.
+        let want = [
+            (17, "Invalid Checksum"),
+            (10, "error in middle of record"),
+            (2, "missing start of fragmented record"),
+        ];
+        let reports = reports.borrow();
+        assert_eq!(want.len(), reports.len());
+        for (w, r) in want.iter().zip(reports.iter()) {
+            assert_eq!(w.0, r.0);
+            assert_eq!(Status::new(StatusCode::Corruption, w.1), r.1);
+        }
+    }
+
+    #[test]
+    fn test_reader_truncated_tail() {
+        let mut lw = LogWriter::new(Vec::new());
+        lw.add_record(b"first record").unwrap();
+        lw.add_record(b"second record").unwrap();
+        let len = lw.dst.len();
+
+        for cut in &[3, HEADER_SIZE + 3] {
+            let reports = share(vec![]);
+            let mut lr = LogReader::new(&lw.dst[..len - cut], true);
+            lr.set_reporter(Box::new(TestReporter(reports.clone())));
+            assert_eq!(vec![b"first record".to_vec()], read_all(&mut lr));
+            let reports = reports.borrow();
+            assert_eq!(1, reports.len());
+            assert_eq!(HEADER_SIZE + 13 - cut, reports[0].0);
+        }
+
+        // Without a reporter, the corruption is returned.
+        let mut lr = LogReader::new(&lw.dst[..len - 3], true);
+        let mut dst = vec![];
+        assert_eq!(12, lr.read(&mut dst).unwrap());
+        assert_eq!(
+            err(StatusCode::Corruption, "truncated record at end of file"),
+            lr.read(&mut dst)
+        );
+        assert_eq!(0, lr.read(&mut dst).unwrap());
+
+        // Unless the truncated tail is ignored.
+        for cut in &[3, HEADER_SIZE + 3] {
+            let mut lr = LogReader::new(&lw.dst[..len - cut], true);
+            lr.ignore_truncated_tail();
+            assert_eq!(12, lr.read(&mut dst).unwrap());
+            assert_eq!(0, lr.read(&mut dst).unwrap());
+        }
+    }
 }
@@ -9,6 +9,7 @@ use std::collections::HashMap;
 use std::io::{self, Read, Write};
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
 /// BufferBackedFile is a simple type implementing RandomAccess on a Vec<u8>.
@@ -30,14 +31,22 @@ impl RandomAccess for BufferBackedFile {
     }
 }
 
-/// A MemFile holds a shared, concurrency-safe buffer. It can be shared among several
-/// MemFileReaders and MemFileWriters, each with an independent offset.
+/// A MemFile holds a shared, concurrency-safe buffer and the time of its last modification. It
+/// can be shared among several MemFileReaders and MemFileWriters, each with an independent
+/// offset.
 #[derive(Clone)]
-pub struct MemFile(Arc<Mutex<BufferBackedFile>>);
+pub struct MemFile(Arc<Mutex<BufferBackedFile>>, Arc<AtomicU64>);
 
 impl MemFile {
     fn new() -> MemFile {
-        MemFile(Arc::new(Mutex::new(Vec::new())))
+        MemFile(
+            Arc::new(Mutex::new(Vec::new())),
+            Arc::new(AtomicU64::new(micros())),
+        )
+    }
+
+    fn touch(&self) {
+        self.1.store(micros(), Ordering::Relaxed);
     }
 }
 
@@ -102,6 +111,7 @@ impl Write for MemFileWriter {
             }
         }
         self.1 += src.len();
+        self.0.touch();
         Ok(src.len())
     }
     fn flush(&mut self) -> io::Result<()> {
@@ -162,6 +172,7 @@ impl MemFS {
         let f = self.open(p, true)?;
         if truncate {
             f.0.lock().unwrap().clear();
+            f.touch();
         }
         Ok(Box::new(MemFileWriter::new(f, append)))
     }
@@ -195,6 +206,16 @@ impl MemFS {
             ),
         }
     }
+    fn modified_(&self, p: &Path) -> Result<u64> {
+        let fs = self.store.lock()?;
+        match fs.get(path_to_str(p)) {
+            Some(e) => Ok(e.f.1.load(Ordering::Relaxed)),
+            None => err(
+                StatusCode::NotFound,
+                &format!("modified_micros: file not found: {}", path_to_str(p)),
+            ),
+        }
+    }
     fn delete_(&self, p: &Path) -> Result<()> {
         let mut fs = self.store.lock()?;
         match fs.entry(path_to_string(p)) {
@@ -208,6 +229,20 @@ impl MemFS {
             ),
         }
     }
+    fn link_(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut fs = self.store.lock()?;
+        let f = match fs.get(path_to_str(from)) {
+            Some(entry) => entry.f.clone(),
+            None => {
+                return err(
+                    StatusCode::NotFound,
+                    &format!("link: file not found: {}", path_to_str(from)),
+                )
+            }
+        };
+        fs.insert(path_to_string(to), MemFSEntry { f, locked: false });
+        Ok(())
+    }
     fn rename_(&self, from: &Path, to: &Path) -> Result<()> {
         let mut fs = self.store.lock()?;
         match fs.remove(path_to_str(from)) {
@@ -308,6 +343,9 @@ impl Env for MemEnv {
     fn size_of(&self, p: &Path) -> Result<usize> {
         self.0.size_of_(p)
     }
+    fn modified_micros(&self, p: &Path) -> Result<u64> {
+        self.0.modified_(p)
+    }
 
     fn delete(&self, p: &Path) -> Result<()> {
         self.0.delete_(p)
@@ -329,6 +367,9 @@ impl Env for MemEnv {
     fn rename(&self, old: &Path, new: &Path) -> Result<()> {
         self.0.rename_(old, new)
     }
+    fn link(&self, old: &Path, new: &Path) -> Result<()> {
+        self.0.link_(old, new)
+    }
 
     fn lock(&self, p: &Path) -> Result<FileLock> {
         self.0.lock_(p)
@@ -356,7 +397,9 @@ mod tests {
     use crate::env;
 
     fn new_memfile(v: Vec<u8>) -> MemFile {
-        MemFile(Arc::new(Mutex::new(v)))
+        let f = MemFile::new();
+        *f.0.lock().unwrap() = v;
+        f
     }
 
     #[test]
@@ -502,6 +545,18 @@ mod tests {
         assert!(fs.size_of_(&path).is_err());
 
         assert!(fs.rename_(&nonexist, &path).is_err());
+
+        // link_ file.
+        fs.link_(&newpath, &path).unwrap();
+        assert_eq!(fs.size_of_(&path).unwrap(), 5);
+        {
+            let mut w = fs.open_w(&newpath, true, false).unwrap();
+            write!(w, "!").unwrap();
+        }
+        assert_eq!(fs.size_of_(&path).unwrap(), 6);
+        fs.delete_(&newpath).unwrap();
+        assert_eq!(fs.size_of_(&path).unwrap(), 6);
+        assert!(fs.link_(&nonexist, &newpath).is_err());
     }
 
     fn s2p(x: &str) -> PathBuf {
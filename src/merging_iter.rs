@@ -1,4 +1,5 @@
 use crate::cmp::Cmp;
+use crate::error::Result;
 use crate::types::{current_key_val, Direction, LdbIterator};
 
 use std::cmp::Ordering;
@@ -191,6 +192,12 @@ impl LdbIterator for MergingIter {
             false
         }
     }
+    fn status(&self) -> Result<()> {
+        for it in self.iters.iter() {
+            it.status()?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -1,12 +1,17 @@
 use crate::block::Block;
-use crate::cache::Cache;
+use crate::cache::{Cache, EvictionPolicy};
 use crate::cmp::{Cmp, DefaultCmp};
 use crate::disk_env;
 use crate::env::Env;
+use crate::error::{err, Result, StatusCode};
+use crate::event_listener::EventListener;
 use crate::filter;
-use crate::infolog::{self, Logger};
+use crate::infolog::{self, InfoLogger, LogLevel};
+use crate::log::CorruptionReporter;
 use crate::mem_env::MemEnv;
-use crate::types::{share, Shared};
+use crate::rate_limiter::RateLimiter;
+use crate::statistics::Statistics;
+use crate::types::{share, Shared, NUM_LEVELS};
 
 use std::default::Default;
 use std::rc::Rc;
@@ -16,6 +21,7 @@ const MB: usize = KB * KB;
 
 const BLOCK_MAX_SIZE: usize = 4 * KB;
 const BLOCK_CACHE_CAPACITY: usize = 8 * MB;
+const BLOCK_CACHE_SHARD_BITS: usize = 4;
 const WRITE_BUFFER_SIZE: usize = 4 * MB;
 const DEFAULT_BITS_PER_KEY: u32 = 10; // NOTE: This may need to be optimized.
 
@@ -33,26 +39,153 @@ pub fn int_to_compressiontype(i: u32) -> Option<CompressionType> {
     }
 }
 
+/// ChecksumType is the algorithm used for the checksums of table blocks. It is recorded in the
+/// table footer; tables written before it was introduced use CRC32C.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChecksumType {
+    /// The masked CRC32C sum, the original format.
+    Crc32c = 0,
+    /// The lower 32 bits of the XXH3 hash, which is faster to compute than CRC32C on CPUs
+    /// without hardware support for the latter.
+    Xxh3 = 1,
+}
+
+pub fn int_to_checksumtype(i: u8) -> Option<ChecksumType> {
+    match i {
+        0 => Some(ChecksumType::Crc32c),
+        1 => Some(ChecksumType::Xxh3),
+        _ => None,
+    }
+}
+
+/// CompactionStyle determines how tables are organized and merged.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompactionStyle {
+    /// Tables are moved through the levels, with every level being much larger than its parent.
+    Level,
+    /// All tables stay in L0, each forming a sorted run; runs of similar size are merged. This
+    /// reduces write amplification at the expense of space amplification and read performance.
+    Universal,
+}
+
+/// WalRecoveryMode determines how corrupted records in log files are treated when opening a
+/// database.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WalRecoveryMode {
+    /// Stop reading a log file at its first corruption, dropping the rest of it, but go on
+    /// replaying later log files. This is how recovery worked before the other modes were added.
+    DropCorruptedLogTails,
+    /// Fail if corrupted records are followed by intact ones; corruption at the end of a log file
+    /// is expected after a crash and ignored.
+    TolerateCorruptedTailRecords,
+    /// Fail on any corruption.
+    AbsoluteConsistency,
+    /// Stop replaying at the first corruption, recovering the database to a consistent state
+    /// before it.
+    PointInTimeRecovery,
+    /// Skip corrupted records and recover all others.
+    SkipAnyCorruptedRecords,
+}
+
 /// Options contains general parameters for a LevelDB instance. Most of the names are
 /// self-explanatory; the defaults are defined in the `Default` implementation.
 #[derive(Clone)]
 pub struct Options {
     pub cmp: Rc<Box<dyn Cmp>>,
     pub env: Rc<Box<dyn Env>>,
-    pub log: Option<Shared<Logger>>,
+    /// Receives informational messages. If unset, messages are written to the file `LOG` in the
+    /// database directory.
+    pub log: Option<Shared<Box<dyn InfoLogger>>>,
+    /// The minimum level of messages written to `LOG`.
+    pub info_log_level: LogLevel,
+    /// `LOG` is rotated once it is larger than this many bytes; 0 means no limit.
+    pub max_log_file_size: usize,
+    /// `LOG` is rotated once it is older than this many seconds; 0 means no limit.
+    pub log_file_time_to_roll: u64,
+    /// The number of rotated log files (`LOG.old.<micros>`) to keep.
+    pub keep_log_file_num: usize,
     pub create_if_missing: bool,
     pub error_if_exists: bool,
+    /// Fail opening the database if a log file or the manifest is corrupted, and stop reading at
+    /// corrupted table blocks instead of skipping them. Such corruptions put the database into
+    /// an error state in which writes and compactions fail.
     pub paranoid_checks: bool,
     pub write_buffer_size: usize,
     pub max_open_files: usize,
+    /// Read tables through memory mappings (see `Env::open_mmap_file()`). At most
+    /// `max_open_files` tables are mapped at once; further ones are read using normal I/O.
+    pub allow_mmap_reads: bool,
+    /// The largest amount of data (in bytes) that iterators read ahead of the current block once
+    /// they read a table sequentially. 0 disables reading ahead.
+    pub max_readahead_size: usize,
+    /// Read tables bypassing the page cache (see `Env::open_direct_random_access_file()`).
+    /// Can't be combined with `allow_mmap_reads`.
+    pub use_direct_reads: bool,
+    /// Write the tables of flushes and compactions bypassing the page cache (see
+    /// `Env::open_direct_writable_file()`).
+    pub use_direct_io_for_flush_and_compaction: bool,
+    /// The target size of table files written to L1. Tables in deeper levels are larger by
+    /// `target_file_size_multiplier` per level.
     pub max_file_size: usize,
+    pub target_file_size_multiplier: usize,
+    /// The maximum total size of tables in L1; deeper levels can hold
+    /// `max_bytes_for_level_multiplier` times more than their parent level.
+    pub max_bytes_for_level_base: usize,
+    pub max_bytes_for_level_multiplier: f64,
+    /// The number of L0 tables at which L0 is compacted.
+    pub level0_file_num_compaction_trigger: usize,
+    /// The deepest level a table written from a memtable may be placed in, if it doesn't overlap
+    /// with other tables.
+    pub max_mem_compaction_level: usize,
+    pub compaction_style: CompactionStyle,
+    /// With universal compaction: A run is merged with the newer runs if it is at most this many
+    /// percent larger than all of them together.
+    pub universal_size_ratio: usize,
+    /// With universal compaction: The minimum number of runs merged because of similar sizes.
+    pub universal_min_merge_width: usize,
+    /// With universal compaction: All runs are merged once the newer runs take up more than this
+    /// many percent of the size of the oldest run.
+    pub universal_max_size_amplification_percent: usize,
+    /// Caches uncompressed blocks; every block is charged by its size in bytes.
     pub block_cache: Shared<Cache<Block>>,
+    /// Keep the index and filter blocks of tables in the block cache, where they are evicted only
+    /// after all data blocks, instead of holding them for as long as a table is open.
+    pub cache_index_and_filter_blocks: bool,
+    /// With `cache_index_and_filter_blocks`: Never evict the index and filter blocks of open L0
+    /// tables from the block cache.
+    pub pin_l0_filter_and_index_blocks_in_cache: bool,
     pub block_size: usize,
     pub block_restart_interval: usize,
     pub compression_type: CompressionType,
+    /// The checksum algorithm for blocks of new tables. Tables written with anything but
+    /// `ChecksumType::Crc32c` can't be read by older versions of this library.
+    pub checksum_type: ChecksumType,
     pub reuse_logs: bool,
     pub reuse_manifest: bool,
     pub filter_policy: filter::BoxedFilterPolicy,
+    /// Obsolete log files are kept for this many seconds, e.g. for `DB::get_updates_since()`. 0
+    /// disables time-based retention.
+    pub wal_ttl_seconds: u64,
+    /// Obsolete log files are kept as long as their total size doesn't exceed this many bytes. 0
+    /// disables size-based retention. If both this and `wal_ttl_seconds` are set, a log file is
+    /// deleted once either budget is exceeded.
+    pub wal_size_limit: usize,
+    /// Defaults to `DropCorruptedLogTails`. Use `PointInTimeRecovery` to also skip the log files
+    /// after a corruption, so that the recovered state doesn't have gaps.
+    pub wal_recovery_mode: WalRecoveryMode,
+    /// Write the memtable to a table when closing the database, so that opening it doesn't
+    /// require replaying the log.
+    pub flush_memtable_on_close: bool,
+    /// Notified about corrupted log data found during recovery.
+    pub corruption_reporter: Option<Shared<Box<dyn CorruptionReporter>>>,
+    /// Throttles the table writes of flushes and compactions.
+    pub rate_limiter: Option<Shared<RateLimiter>>,
+    /// Also charge the entries read by compactions to the rate limiter's compaction budget.
+    pub rate_limit_compaction_reads: bool,
+    /// Notified about flushes, compactions and other background events.
+    pub listeners: Vec<Rc<Box<dyn EventListener>>>,
+    /// Collects counters and latencies; can be shared among databases.
+    pub statistics: Option<Shared<Statistics>>,
 }
 
 impl Default for Options {
@@ -61,22 +194,132 @@ impl Default for Options {
             cmp: Rc::new(Box::new(DefaultCmp)),
             env: Rc::new(Box::new(disk_env::PosixDiskEnv::new())),
             log: None,
+            info_log_level: LogLevel::Info,
+            max_log_file_size: 0,
+            log_file_time_to_roll: 0,
+            keep_log_file_num: 1000,
             create_if_missing: true,
             error_if_exists: false,
             paranoid_checks: false,
             write_buffer_size: WRITE_BUFFER_SIZE,
             max_open_files: 1 << 10,
+            allow_mmap_reads: false,
+            max_readahead_size: 256 * KB,
+            use_direct_reads: false,
+            use_direct_io_for_flush_and_compaction: false,
             max_file_size: 2 << 20,
-            // 2000 elements by default
-            block_cache: share(Cache::new(BLOCK_CACHE_CAPACITY / BLOCK_MAX_SIZE)),
+            target_file_size_multiplier: 1,
+            max_bytes_for_level_base: 10 * MB,
+            max_bytes_for_level_multiplier: 10.0,
+            level0_file_num_compaction_trigger: 4,
+            max_mem_compaction_level: 2,
+            compaction_style: CompactionStyle::Level,
+            universal_size_ratio: 1,
+            universal_min_merge_width: 2,
+            universal_max_size_amplification_percent: 200,
+            // 8 MiB of blocks by default
+            block_cache: share(Cache::new_sharded(
+                BLOCK_CACHE_CAPACITY,
+                BLOCK_CACHE_SHARD_BITS,
+            )),
+            cache_index_and_filter_blocks: false,
+            pin_l0_filter_and_index_blocks_in_cache: false,
             block_size: BLOCK_MAX_SIZE,
             block_restart_interval: 16,
             reuse_logs: true,
             reuse_manifest: true,
             compression_type: CompressionType::CompressionNone,
+            checksum_type: ChecksumType::Crc32c,
             filter_policy: Rc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+            wal_ttl_seconds: 0,
+            wal_size_limit: 0,
+            wal_recovery_mode: WalRecoveryMode::DropCorruptedLogTails,
+            flush_memtable_on_close: false,
+            corruption_reporter: None,
+            rate_limiter: None,
+            rate_limit_compaction_reads: false,
+            listeners: vec![],
+            statistics: None,
+        }
+    }
+}
+
+impl Options {
+    /// Returns the maximum total size of the tables in `level`, which must be at least 1.
+    pub fn max_bytes_for_level(&self, level: usize) -> f64 {
+        assert!(level >= 1);
+        let mut max_bytes = self.max_bytes_for_level_base as f64;
+        for _ in 1..level {
+            max_bytes *= self.max_bytes_for_level_multiplier;
+        }
+        max_bytes
+    }
+
+    /// Returns the target size of table files written to `level`.
+    pub fn max_file_size_for_level(&self, level: usize) -> usize {
+        let mut size = self.max_file_size;
+        for _ in 1..level {
+            size = size.saturating_mul(self.target_file_size_multiplier);
         }
+        size
+    }
+
+    /// Replace the block cache with a new one holding up to `capacity` bytes of blocks.
+    pub fn set_block_cache_capacity(&mut self, capacity: usize) {
+        let policy = self.block_cache.borrow().policy();
+        self.block_cache = share(Cache::new_with_policy(
+            capacity,
+            BLOCK_CACHE_SHARD_BITS,
+            policy,
+        ));
     }
+
+    /// Replace the block cache with a new one of the same capacity that evicts blocks according
+    /// to `policy`. `EvictionPolicy::SegmentedLru` keeps frequently read blocks cached during
+    /// long scans.
+    pub fn set_block_cache_policy(&mut self, policy: EvictionPolicy) {
+        let capacity = self.block_cache.borrow().cap();
+        self.block_cache = share(Cache::new_with_policy(
+            capacity,
+            BLOCK_CACHE_SHARD_BITS,
+            policy,
+        ));
+    }
+
+    /// Checks that the level sizing and compaction parameters are usable.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg| err(StatusCode::InvalidArgument, msg);
+        if self.max_file_size == 0 || self.target_file_size_multiplier == 0 {
+            invalid("max_file_size and target_file_size_multiplier must be positive")
+        } else if self.max_bytes_for_level_base == 0 {
+            invalid("max_bytes_for_level_base must be positive")
+        } else if !self.max_bytes_for_level_multiplier.is_finite()
+            || self.max_bytes_for_level_multiplier < 1.0
+        {
+            invalid("max_bytes_for_level_multiplier must be at least 1")
+        } else if self.level0_file_num_compaction_trigger == 0 {
+            invalid("level0_file_num_compaction_trigger must be positive")
+        } else if self.max_mem_compaction_level >= NUM_LEVELS {
+            invalid("max_mem_compaction_level must be less than the number of levels")
+        } else if self.allow_mmap_reads && self.use_direct_reads {
+            invalid("allow_mmap_reads and use_direct_reads are mutually exclusive")
+        } else if self.universal_min_merge_width < 2 {
+            invalid("universal_min_merge_width must be at least 2")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// WriteOptions control individual writes.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct WriteOptions {
+    /// Flush the log file after writing the batch.
+    pub sync: bool,
+    /// Don't write the batch to the log file. Such writes are lost if the database is not closed
+    /// properly before their memtable has been written to a table; they are also not returned by
+    /// `DB::get_updates_since()`.
+    pub disable_wal: bool,
 }
 
 /// Returns Options that will cause a database to exist purely in-memory instead of being stored on
@@ -90,6 +333,6 @@ pub fn in_memory() -> Options {
 pub fn for_test() -> Options {
     let mut o = Options::default();
     o.env = Rc::new(Box::new(MemEnv::new()));
-    o.log = Some(share(infolog::stderr()));
+    o.log = Some(share(Box::new(infolog::stderr(o.env.clone()))));
     o
 }
@@ -0,0 +1,209 @@
+//! rate_limiter contains RateLimiter, which throttles the I/O of flushes and compactions so that
+//! it doesn't starve foreground reads and writes.
+
+use crate::env::Env;
+use crate::types::Shared;
+
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// The longest sleep in a single call to `Env::sleep_for()`.
+const MAX_SLEEP_MICROS: u64 = 100_000;
+
+/// IoSource identifies which budget of a RateLimiter an I/O request is charged to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IoSource {
+    /// Writing memtables to L0 tables.
+    Flush,
+    /// Reading and writing tables during compactions.
+    Compaction,
+}
+
+/// A token bucket refilled at a constant rate. A request may take more tokens than are
+/// available; the caller then waits until the debt has been paid off.
+struct Bucket {
+    bytes_per_sec: usize,
+    available: f64,
+    last_refill: u64,
+    total_bytes: usize,
+}
+
+impl Bucket {
+    fn new(bytes_per_sec: usize, now: u64) -> Bucket {
+        Bucket {
+            bytes_per_sec,
+            available: 0.0,
+            last_refill: now,
+            total_bytes: 0,
+        }
+    }
+
+    /// The number of bytes that can be used at once after a period of inactivity.
+    fn burst(&self) -> f64 {
+        self.bytes_per_sec as f64 / 10.0
+    }
+
+    fn refill(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.last_refill);
+        self.available += elapsed as f64 * self.bytes_per_sec as f64 / 1e6;
+        if self.available > self.burst() {
+            self.available = self.burst();
+        }
+        self.last_refill = now;
+    }
+}
+
+/// RateLimiter limits the throughput of background I/O, with separate budgets (in bytes per
+/// second) for flushes and compactions. A budget of 0 means unlimited. Requests exceeding the
+/// budget block the calling thread using `Env::sleep_for()`.
+///
+/// A RateLimiter is installed using `Options::rate_limiter`, and can be shared among databases
+/// to limit their combined I/O.
+pub struct RateLimiter {
+    env: Rc<Box<dyn Env>>,
+    flush: Bucket,
+    compaction: Bucket,
+}
+
+impl RateLimiter {
+    pub fn new(
+        env: Rc<Box<dyn Env>>,
+        flush_bytes_per_sec: usize,
+        compaction_bytes_per_sec: usize,
+    ) -> RateLimiter {
+        let now = env.micros();
+        RateLimiter {
+            env,
+            flush: Bucket::new(flush_bytes_per_sec, now),
+            compaction: Bucket::new(compaction_bytes_per_sec, now),
+        }
+    }
+
+    fn bucket(&self, src: IoSource) -> &Bucket {
+        match src {
+            IoSource::Flush => &self.flush,
+            IoSource::Compaction => &self.compaction,
+        }
+    }
+
+    fn bucket_mut(&mut self, src: IoSource) -> &mut Bucket {
+        match src {
+            IoSource::Flush => &mut self.flush,
+            IoSource::Compaction => &mut self.compaction,
+        }
+    }
+
+    /// Change the budget of `src`. This takes effect for the next request.
+    pub fn set_bytes_per_sec(&mut self, src: IoSource, bytes_per_sec: usize) {
+        self.bucket_mut(src).bytes_per_sec = bytes_per_sec;
+    }
+
+    /// Returns the budget of `src`.
+    pub fn bytes_per_sec(&self, src: IoSource) -> usize {
+        self.bucket(src).bytes_per_sec
+    }
+
+    /// Returns the number of bytes requested from `src`'s budget so far.
+    pub fn total_bytes(&self, src: IoSource) -> usize {
+        self.bucket(src).total_bytes
+    }
+
+    /// Take `bytes` from the budget of `src`, waiting until they are available.
+    pub fn request(&mut self, src: IoSource, bytes: usize) {
+        let now = self.env.micros();
+        let bucket = self.bucket_mut(src);
+        bucket.total_bytes += bytes;
+        if bucket.bytes_per_sec == 0 {
+            return;
+        }
+        bucket.refill(now);
+        bucket.available -= bytes as f64;
+        if bucket.available >= 0.0 {
+            return;
+        }
+
+        // Sleep until the debt is paid off. The time slept is accounted for by the next refill.
+        let mut wait = (-bucket.available * 1e6 / bucket.bytes_per_sec as f64).ceil() as u64;
+        while wait > 0 {
+            let chunk = wait.min(MAX_SLEEP_MICROS);
+            self.env.sleep_for(chunk as u32);
+            wait -= chunk;
+        }
+    }
+}
+
+/// RateLimitedWriter charges all writes to a budget of a RateLimiter before passing them on.
+pub struct RateLimitedWriter<W: Write> {
+    w: W,
+    limiter: Shared<RateLimiter>,
+    src: IoSource,
+}
+
+impl<W: Write> RateLimitedWriter<W> {
+    pub fn new(w: W, limiter: Shared<RateLimiter>, src: IoSource) -> RateLimitedWriter<W> {
+        RateLimitedWriter { w, limiter, src }
+    }
+}
+
+impl<W: Write> Write for RateLimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.limiter.borrow_mut().request(self.src, buf.len());
+        self.w.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// Wrap `w` in a RateLimitedWriter if a rate limiter is set.
+pub fn rate_limited(
+    w: Box<dyn Write>,
+    limiter: &Option<Shared<RateLimiter>>,
+    src: IoSource,
+) -> Box<dyn Write> {
+    match limiter {
+        Some(limiter) => Box::new(RateLimitedWriter::new(w, limiter.clone(), src)),
+        None => w,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_env::MemEnv;
+    use crate::types::share;
+
+    #[test]
+    fn test_rate_limiter() {
+        let env: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        let mut rl = RateLimiter::new(env.clone(), 1 << 20, 0);
+
+        // Unlimited.
+        let start = env.micros();
+        rl.request(IoSource::Compaction, 1 << 30);
+        assert_eq!(1 << 30, rl.total_bytes(IoSource::Compaction));
+
+        // 200 KiB at 1 MiB/s take at least 195 ms.
+        rl.request(IoSource::Flush, 100 << 10);
+        rl.request(IoSource::Flush, 100 << 10);
+        assert!(env.micros() - start >= 195_000);
+        assert_eq!(200 << 10, rl.total_bytes(IoSource::Flush));
+
+        rl.set_bytes_per_sec(IoSource::Flush, 0);
+        assert_eq!(0, rl.bytes_per_sec(IoSource::Flush));
+        let start = env.micros();
+        rl.request(IoSource::Flush, 1 << 30);
+        assert!(env.micros() - start < 100_000);
+    }
+
+    #[test]
+    fn test_rate_limited_writer() {
+        let env: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        let rl = share(RateLimiter::new(env, 0, 0));
+        let mut w = rate_limited(Box::new(vec![]), &Some(rl.clone()), IoSource::Compaction);
+        w.write_all(&[1, 2, 3]).unwrap();
+        w.write_all(&[4]).unwrap();
+        assert_eq!(4, rl.borrow().total_bytes(IoSource::Compaction));
+        assert_eq!(0, rl.borrow().total_bytes(IoSource::Flush));
+    }
+}
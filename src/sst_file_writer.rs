@@ -0,0 +1,135 @@
+//! sst_file_writer contains SstFileWriter, which builds table files outside of a database. Such
+//! files can then be added to a database using `DB::ingest_external_file()`, which is much cheaper
+//! than loading large amounts of sorted data through `put()`.
+
+use crate::error::{err, Result, StatusCode};
+use crate::key_types::{LookupKey, ValueType};
+use crate::options::Options;
+use crate::table_builder::TableBuilder;
+
+use std::cmp::Ordering;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// SstFileWriter writes a table file containing user keys in strictly increasing order (as
+/// determined by the comparator in the supplied options). All entries are stored with sequence
+/// number 0; the database assigns a real sequence number when the file is ingested.
+pub struct SstFileWriter {
+    opt: Options,
+    path: PathBuf,
+    builder: Option<TableBuilder<BufWriter<Box<dyn Write>>>>,
+    last_key: Option<Vec<u8>>,
+}
+
+impl SstFileWriter {
+    /// Create a new table file at `path`, using the environment, comparator, filter policy and
+    /// block settings in `opt`. An existing file at `path` is replaced; it's deleted first, so
+    /// that a database that it has been ingested into keeps its copy.
+    pub fn create<P: AsRef<Path>>(opt: Options, path: P) -> Result<SstFileWriter> {
+        let path = path.as_ref().to_owned();
+        if opt.env.exists(&path)? {
+            opt.env.delete(&path)?;
+        }
+        let f = opt.env.open_writable_file(&path)?;
+        Ok(SstFileWriter {
+            builder: Some(TableBuilder::new(opt.clone(), BufWriter::new(f))),
+            opt,
+            path,
+            last_key: None,
+        })
+    }
+
+    /// Add an entry. Its key must be greater than the previously added key.
+    pub fn put(&mut self, k: &[u8], v: &[u8]) -> Result<()> {
+        self.add(k, v, ValueType::TypeValue)
+    }
+
+    /// Add a deletion marker for a key. Its key must be greater than the previously added key.
+    pub fn delete(&mut self, k: &[u8]) -> Result<()> {
+        self.add(k, &[], ValueType::TypeDeletion)
+    }
+
+    fn add(&mut self, k: &[u8], v: &[u8], t: ValueType) -> Result<()> {
+        if let Some(ref last) = self.last_key {
+            if self.opt.cmp.cmp(last, k) != Ordering::Less {
+                return err(
+                    StatusCode::InvalidArgument,
+                    "keys must be added to SstFileWriter in strictly increasing order",
+                );
+            }
+        }
+        let ikey = LookupKey::new_full(k, 0, t);
+        self.builder.as_mut().unwrap().add(ikey.internal_key(), v)?;
+        self.last_key = Some(k.to_vec());
+        Ok(())
+    }
+
+    /// Returns the number of entries added so far.
+    pub fn entries(&self) -> usize {
+        self.builder.as_ref().unwrap().entries()
+    }
+
+    /// Write out the remaining blocks and the table footer. Returns the size of the file. An empty
+    /// table is not useful for ingestion; writing one is therefore an error.
+    pub fn finish(mut self) -> Result<usize> {
+        if self.last_key.is_none() {
+            let _ = self.opt.env.delete(&self.path);
+            return err(
+                StatusCode::InvalidArgument,
+                "no entries added to SstFileWriter",
+            );
+        }
+        self.builder.take().unwrap().finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_types::parse_internal_key;
+    use crate::options;
+    use crate::table_reader::Table;
+    use crate::test_util::LdbIteratorIter;
+
+    use std::rc::Rc;
+
+    #[test]
+    fn test_sst_file_writer() {
+        let opt = options::for_test();
+        let path = Path::new("ext.sst");
+        let mut w = SstFileWriter::create(opt.clone(), path).unwrap();
+        w.put(b"abc", b"def").unwrap();
+        w.delete(b"abd").unwrap();
+        w.put(b"xyz", b"123").unwrap();
+        assert!(w.put(b"xyz", b"456").is_err());
+        assert!(w.put(b"aaa", b"456").is_err());
+        assert_eq!(3, w.entries());
+        let size = w.finish().unwrap();
+        assert_eq!(size, opt.env.size_of(path).unwrap());
+
+        let file = Rc::new(opt.env.open_random_access_file(path).unwrap());
+        let t = Table::new(opt.clone(), file, size).unwrap();
+        let entries: Vec<_> = LdbIteratorIter::wrap(&mut t.iter())
+            .map(|(k, v)| {
+                let (typ, seq, ukey) = parse_internal_key(&k);
+                (typ, seq, ukey.to_vec(), v)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (ValueType::TypeValue, 0, b"abc".to_vec(), b"def".to_vec()),
+                (ValueType::TypeDeletion, 0, b"abd".to_vec(), vec![]),
+                (ValueType::TypeValue, 0, b"xyz".to_vec(), b"123".to_vec()),
+            ],
+            entries
+        );
+    }
+
+    #[test]
+    fn test_sst_file_writer_empty() {
+        let opt = options::for_test();
+        let w = SstFileWriter::create(opt.clone(), "empty.sst").unwrap();
+        assert!(w.finish().is_err());
+        assert!(!opt.env.exists(Path::new("empty.sst")).unwrap());
+    }
+}
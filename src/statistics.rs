@@ -0,0 +1,314 @@
+//! statistics contains Statistics, which counts events and measures latencies of database
+//! operations. Statistics are only collected if `Options::statistics` is set.
+
+use crate::options::Options;
+use crate::types::NUM_LEVELS;
+
+use std::fmt;
+
+/// Ticker identifies a counter.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Ticker {
+    BlockCacheHit,
+    BlockCacheMiss,
+    /// Table lookups skipped because the filter ruled out the key.
+    BloomFilterUseful,
+    /// Table lookups for which the filter reported a possible match.
+    BloomFilterPositive,
+    /// Bytes of write batches applied to the database.
+    BytesWritten,
+    /// Bytes of values returned by `get()`.
+    BytesRead,
+    /// Lookups answered by a memtable, with a value or a deletion.
+    MemtableHit,
+    MemtableMiss,
+}
+
+const TICKERS: [(Ticker, &str); 8] = [
+    (Ticker::BlockCacheHit, "block.cache.hit"),
+    (Ticker::BlockCacheMiss, "block.cache.miss"),
+    (Ticker::BloomFilterUseful, "bloom.filter.useful"),
+    (Ticker::BloomFilterPositive, "bloom.filter.positive"),
+    (Ticker::BytesWritten, "bytes.written"),
+    (Ticker::BytesRead, "bytes.read"),
+    (Ticker::MemtableHit, "memtable.hit"),
+    (Ticker::MemtableMiss, "memtable.miss"),
+];
+
+/// Histogram identifies a distribution of durations, in microseconds.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Histogram {
+    Get,
+    Write,
+    /// Seeks of DBIterators.
+    Seek,
+    /// The time spent merging tables in compactions.
+    Compaction,
+}
+
+const HISTOGRAMS: [(Histogram, &str); 4] = [
+    (Histogram::Get, "get.micros"),
+    (Histogram::Write, "write.micros"),
+    (Histogram::Seek, "seek.micros"),
+    (Histogram::Compaction, "compaction.micros"),
+];
+
+// Values below 4 have their own bucket; every larger power of two is split into 4 buckets.
+const NUM_BUCKETS: usize = 4 + 62 * 4;
+
+fn bucket_index(v: u64) -> usize {
+    if v < 4 {
+        v as usize
+    } else {
+        let exp = 63 - v.leading_zeros() as usize;
+        let sub = ((v >> (exp - 2)) & 3) as usize;
+        4 + (exp - 2) * 4 + sub
+    }
+}
+
+/// Returns the smallest value in bucket `ix`, and the smallest value of the next bucket.
+fn bucket_limits(ix: usize) -> (u64, u64) {
+    if ix < 4 {
+        (ix as u64, ix as u64 + 1)
+    } else {
+        let shift = (ix - 4) / 4;
+        let lower = (4 + (ix - 4) as u64 % 4) << shift;
+        (lower, lower.saturating_add(1 << shift))
+    }
+}
+
+/// HistogramData summarizes recorded values with a relative error of at most 25%.
+#[derive(Clone, Debug)]
+pub struct HistogramData {
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+    buckets: Vec<u64>,
+}
+
+impl HistogramData {
+    fn new() -> HistogramData {
+        HistogramData {
+            count: 0,
+            sum: 0,
+            min: 0,
+            max: 0,
+            buckets: vec![0; NUM_BUCKETS],
+        }
+    }
+
+    fn add(&mut self, v: u64) {
+        if self.count == 0 || v < self.min {
+            self.min = v;
+        }
+        if v > self.max {
+            self.max = v;
+        }
+        self.count += 1;
+        self.sum = self.sum.saturating_add(v);
+        self.buckets[bucket_index(v)] += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn average(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// Returns an estimate of the value below which `p` percent of the recorded values are.
+    pub fn percentile(&self, p: f64) -> f64 {
+        let threshold = self.count as f64 * p / 100.0;
+        let mut cumulative = 0.0;
+        for (ix, &n) in self.buckets.iter().enumerate() {
+            if n == 0 {
+                continue;
+            }
+            if cumulative + n as f64 >= threshold {
+                // Assume that the values are spread evenly within the bucket.
+                let (lower, upper) = bucket_limits(ix);
+                let pos = (threshold - cumulative) / n as f64;
+                let v = lower as f64 + (upper - lower) as f64 * pos;
+                return v.max(self.min as f64).min(self.max as f64);
+            }
+            cumulative += n as f64;
+        }
+        self.max as f64
+    }
+}
+
+impl fmt::Display for HistogramData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "count={} avg={:.1} min={} p50={:.1} p95={:.1} p99={:.1} max={}",
+            self.count,
+            self.average(),
+            self.min,
+            self.percentile(50.0),
+            self.percentile(95.0),
+            self.percentile(99.0),
+            self.max
+        )
+    }
+}
+
+/// Statistics holds counters and latency histograms for one or more databases. It can be dumped
+/// as text using its `Display` implementation.
+pub struct Statistics {
+    tickers: [u64; TICKERS.len()],
+    found_in_level: [u64; NUM_LEVELS],
+    histograms: Vec<HistogramData>,
+}
+
+impl Default for Statistics {
+    fn default() -> Statistics {
+        Statistics::new()
+    }
+}
+
+impl Statistics {
+    pub fn new() -> Statistics {
+        Statistics {
+            tickers: [0; TICKERS.len()],
+            found_in_level: [0; NUM_LEVELS],
+            histograms: vec![HistogramData::new(); HISTOGRAMS.len()],
+        }
+    }
+
+    /// Returns the value of the counter `t`.
+    pub fn ticker(&self, t: Ticker) -> u64 {
+        self.tickers[t as usize]
+    }
+
+    pub fn record_tick(&mut self, t: Ticker, n: u64) {
+        self.tickers[t as usize] += n;
+    }
+
+    /// Returns the number of lookups whose key was found in a table at `level`.
+    pub fn found_in_level(&self, level: usize) -> u64 {
+        self.found_in_level[level]
+    }
+
+    pub fn record_found_in_level(&mut self, level: usize) {
+        self.found_in_level[level] += 1;
+    }
+
+    pub fn histogram(&self, h: Histogram) -> &HistogramData {
+        &self.histograms[h as usize]
+    }
+
+    pub fn record_in_histogram(&mut self, h: Histogram, v: u64) {
+        self.histograms[h as usize].add(v);
+    }
+
+    /// Set all counters and histograms to zero.
+    pub fn reset(&mut self) {
+        *self = Statistics::new();
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (t, name) in TICKERS.iter() {
+            writeln!(f, "{}: {}", name, self.ticker(*t))?;
+        }
+        for level in 0..NUM_LEVELS {
+            writeln!(f, "found.l{}: {}", level, self.found_in_level(level))?;
+        }
+        for (h, name) in HISTOGRAMS.iter() {
+            writeln!(f, "{}: {}", name, self.histogram(*h))?;
+        }
+        Ok(())
+    }
+}
+
+/// Increment the counter `t` by `n` if `opt` collects statistics.
+pub fn record_tick(opt: &Options, t: Ticker, n: u64) {
+    if let Some(ref stats) = opt.statistics {
+        stats.borrow_mut().record_tick(t, n);
+    }
+}
+
+/// Returns the current time if `opt` collects statistics, to be passed to `record_time()`.
+pub fn start_timer(opt: &Options) -> Option<u64> {
+    opt.statistics.as_ref().map(|_| opt.env.micros())
+}
+
+/// Record the time elapsed since `start` in the histogram `h`.
+pub fn record_time(opt: &Options, h: Histogram, start: Option<u64>) {
+    if let (Some(stats), Some(start)) = (opt.statistics.as_ref(), start) {
+        let elapsed = opt.env.micros().saturating_sub(start);
+        stats.borrow_mut().record_in_histogram(h, elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statistics_buckets() {
+        for v in [0, 1, 3, 4, 7, 8, 9, 100, 1 << 40, u64::MAX] {
+            let (lower, upper) = bucket_limits(bucket_index(v));
+            assert!(lower <= v && (v < upper || upper == u64::MAX), "{}", v);
+        }
+        assert_eq!(NUM_BUCKETS - 1, bucket_index(u64::MAX));
+        for ix in 1..NUM_BUCKETS {
+            assert_eq!(bucket_limits(ix - 1).1, bucket_limits(ix).0);
+        }
+    }
+
+    #[test]
+    fn test_statistics_histogram() {
+        let mut h = HistogramData::new();
+        assert_eq!(0.0, h.percentile(50.0));
+        for v in 1..=100 {
+            h.add(v);
+        }
+        assert_eq!((100, 5050, 1, 100), (h.count(), h.sum(), h.min(), h.max()));
+        assert_eq!(50.5, h.average());
+        for p in [10.0, 50.0, 90.0, 99.0] {
+            let est = h.percentile(p);
+            assert!((est - p).abs() <= p / 4.0, "p{} = {}", p, est);
+        }
+        assert_eq!(100.0, h.percentile(100.0));
+    }
+
+    #[test]
+    fn test_statistics() {
+        let mut s = Statistics::new();
+        s.record_tick(Ticker::MemtableHit, 2);
+        s.record_tick(Ticker::MemtableHit, 1);
+        s.record_found_in_level(1);
+        s.record_in_histogram(Histogram::Get, 10);
+        assert_eq!(3, s.ticker(Ticker::MemtableHit));
+        assert_eq!(0, s.ticker(Ticker::MemtableMiss));
+        assert_eq!(1, s.found_in_level(1));
+        assert_eq!(1, s.histogram(Histogram::Get).count());
+
+        let text = s.to_string();
+        assert!(text.contains("memtable.hit: 3\n"));
+        assert!(text.contains("found.l1: 1\n"));
+        assert!(text.contains("get.micros: count=1 avg=10.0 min=10"));
+
+        s.reset();
+        assert_eq!(0, s.ticker(Ticker::MemtableHit));
+        assert_eq!(0, s.histogram(Histogram::Get).count());
+    }
+}
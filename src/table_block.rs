@@ -1,18 +1,21 @@
 use crate::block::Block;
 use crate::blockhandle::BlockHandle;
+use crate::checksum;
 use crate::env::RandomAccess;
 use crate::error::{err, Result, StatusCode};
 use crate::filter;
 use crate::filter_block::FilterBlockReader;
-use crate::log::unmask_crc;
-use crate::options::{self, CompressionType, Options};
+use crate::options::{self, ChecksumType, CompressionType, Options};
 use crate::table_builder;
 
-use crc::crc32::{self, Hasher32};
 use integer_encoding::FixedInt;
 
+/// The length of the compression type and checksum following every block.
+pub const BLOCK_TRAILER_LEN: usize =
+    table_builder::TABLE_BLOCK_COMPRESS_LEN + table_builder::TABLE_BLOCK_CKSUM_LEN;
+
 /// Reads the data for the specified block handle from a file.
-fn read_bytes(f: &dyn RandomAccess, location: &BlockHandle) -> Result<Vec<u8>> {
+pub fn read_bytes(f: &dyn RandomAccess, location: &BlockHandle) -> Result<Vec<u8>> {
     let mut buf = vec![0; location.size()];
     f.read_at(location.offset(), &mut buf).map(|_| buf)
 }
@@ -35,32 +38,39 @@ pub fn read_filter_block(
 
 /// Reads a table block from a random-access source.
 /// A table block consists of [bytes..., compress (1B), checksum (4B)]; the handle only refers to
-/// the location and length of [bytes...].
+/// the location and length of [bytes...]. The checksum is computed using `checksum`, the type
+/// recorded in the table's footer.
 pub fn read_table_block(
     opt: Options,
     f: &dyn RandomAccess,
     location: &BlockHandle,
+    checksum: ChecksumType,
 ) -> Result<Block> {
     // The block is denoted by offset and length in BlockHandle. A block in an encoded
-    // table is followed by 1B compression type and 4B checksum.
+    // table is followed by 1B compression type and 4B checksum, which are read together with it.
+    let buf = read_bytes(f, &with_trailer(location))?;
+    decode_table_block(opt, buf, location, checksum)
+}
+
+/// Returns the location of a block's contents together with its trailer.
+pub fn with_trailer(location: &BlockHandle) -> BlockHandle {
+    BlockHandle::new(location.offset(), location.size() + BLOCK_TRAILER_LEN)
+}
+
+/// Verifies and decompresses the table block at `location`, read together with its trailer into
+/// `buf`.
+pub fn decode_table_block(
+    opt: Options,
+    mut buf: Vec<u8>,
+    location: &BlockHandle,
+    checksum: ChecksumType,
+) -> Result<Block> {
     // The checksum refers to the compressed contents.
-    let buf = read_bytes(f, location)?;
-    let compress = read_bytes(
-        f,
-        &BlockHandle::new(
-            location.offset() + location.size(),
-            table_builder::TABLE_BLOCK_COMPRESS_LEN,
-        ),
-    )?;
-    let cksum = read_bytes(
-        f,
-        &BlockHandle::new(
-            location.offset() + location.size() + table_builder::TABLE_BLOCK_COMPRESS_LEN,
-            table_builder::TABLE_BLOCK_CKSUM_LEN,
-        ),
-    )?;
+    let trailer = buf.split_off(location.size());
+    let compress = &trailer[..table_builder::TABLE_BLOCK_COMPRESS_LEN];
+    let cksum = &trailer[table_builder::TABLE_BLOCK_COMPRESS_LEN..];
 
-    if !verify_table_block(&buf, compress[0], unmask_crc(u32::decode_fixed(&cksum))) {
+    if checksum::block_checksum(checksum, &buf, compress[0]) != u32::decode_fixed(cksum) {
         return err(
             StatusCode::Corruption,
             &format!(
@@ -82,11 +92,3 @@ pub fn read_table_block(
         err(StatusCode::InvalidData, "invalid compression type")
     }
 }
-
-/// Verify checksum of block
-fn verify_table_block(data: &[u8], compression: u8, want: u32) -> bool {
-    let mut digest = crc32::Digest::new(crc32::CASTAGNOLI);
-    digest.write(data);
-    digest.write(&[compression; 1]);
-    digest.sum32() == want
-}
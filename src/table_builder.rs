@@ -1,20 +1,18 @@
 use crate::block::BlockContents;
 use crate::block_builder::BlockBuilder;
 use crate::blockhandle::BlockHandle;
+use crate::checksum;
 use crate::cmp::InternalKeyCmp;
 use crate::error::Result;
 use crate::filter::{InternalFilterPolicy, NoFilterPolicy};
 use crate::filter_block::FilterBlockBuilder;
 use crate::key_types::InternalKey;
-use crate::log::mask_crc;
-use crate::options::{CompressionType, Options};
+use crate::options::{self, ChecksumType, CompressionType, Options};
 
 use std::cmp::Ordering;
 use std::io::Write;
 use std::rc::Rc;
 
-use crc::crc32;
-use crc::Hasher32;
 use integer_encoding::FixedIntWriter;
 
 pub const FOOTER_LENGTH: usize = 40;
@@ -30,17 +28,23 @@ pub const TABLE_BLOCK_CKSUM_LEN: usize = 4;
 pub struct Footer {
     pub meta_index: BlockHandle,
     pub index: BlockHandle,
+    pub checksum: ChecksumType,
 }
 
 /// A Table footer contains a pointer to the metaindex block, another pointer to the index block,
-/// and a magic number:
-/// [ { table data ... , METAINDEX blockhandle, INDEX blockhandle, PADDING bytes } = 40 bytes,
-/// MAGIC_FOOTER_ENCODED ]
+/// the checksum type of all blocks and a magic number:
+/// [ { table data ... , METAINDEX blockhandle, INDEX blockhandle, PADDING bytes, CHECKSUM type }
+/// = 40 bytes, MAGIC_FOOTER_ENCODED ]
+///
+/// The checksum type takes the place of the last padding byte, which is 0 (CRC32C) in tables
+/// written before it was introduced. The block handles never reach it, as that would require
+/// offsets beyond 2^56.
 impl Footer {
-    pub fn new(metaix: BlockHandle, index: BlockHandle) -> Footer {
+    pub fn new(metaix: BlockHandle, index: BlockHandle, checksum: ChecksumType) -> Footer {
         Footer {
             meta_index: metaix,
             index,
+            checksum,
         }
     }
 
@@ -49,10 +53,12 @@ impl Footer {
         assert_eq!(&from[FOOTER_LENGTH..], &MAGIC_FOOTER_ENCODED);
         let (meta, metalen) = BlockHandle::decode(&from[0..])?;
         let (ix, _) = BlockHandle::decode(&from[metalen..])?;
+        let checksum = options::int_to_checksumtype(from[FOOTER_LENGTH - 1])?;
 
         Some(Footer {
             meta_index: meta,
             index: ix,
+            checksum,
         })
     }
 
@@ -61,10 +67,12 @@ impl Footer {
 
         let s1 = self.meta_index.encode_to(to);
         let s2 = self.index.encode_to(&mut to[s1..]);
+        assert!(s1 + s2 < FOOTER_LENGTH);
 
         for i in s1 + s2..FOOTER_LENGTH {
             to[i] = 0;
         }
+        to[FOOTER_LENGTH - 1] = self.checksum as u8;
         for i in FOOTER_LENGTH..FULL_FOOTER_LENGTH {
             to[i] = MAGIC_FOOTER_ENCODED[i - FOOTER_LENGTH];
         }
@@ -77,7 +85,8 @@ impl Footer {
 /// the `block` module.
 ///
 /// The FOOTER consists of a BlockHandle that points to the metaindex block, another pointing to
-/// the index block, padding to fill up to 40 B and at the end the 8B magic number
+/// the index block, padding to fill up to 40 B including the checksum type, and at the end the 8B
+/// magic number
 /// 0xdb4775248b80fb57.
 
 pub struct TableBuilder<Dst: Write> {
@@ -204,14 +213,11 @@ impl<Dst: Write> TableBuilder<Dst> {
             data = snap::raw::Encoder::new().compress_vec(&data)?;
         }
 
-        let mut digest = crc32::Digest::new(crc32::CASTAGNOLI);
-
-        digest.write(&data);
-        digest.write(&[ctype as u8; TABLE_BLOCK_COMPRESS_LEN]);
+        let cksum = checksum::block_checksum(self.opt.checksum_type, &data, ctype as u8);
 
         self.dst.write(&data)?;
         self.dst.write(&[ctype as u8; TABLE_BLOCK_COMPRESS_LEN])?;
-        self.dst.write_fixedint(mask_crc(digest.sum32()))?;
+        self.dst.write_fixedint(cksum)?;
 
         let handle = BlockHandle::new(self.offset, data.len());
         self.offset += data.len() + TABLE_BLOCK_COMPRESS_LEN + TABLE_BLOCK_CKSUM_LEN;
@@ -258,7 +264,7 @@ impl<Dst: Write> TableBuilder<Dst> {
         let ix_handle = self.write_block(index_cont, ctype)?;
 
         // write footer.
-        let footer = Footer::new(meta_ix_handle, ix_handle);
+        let footer = Footer::new(meta_ix_handle, ix_handle, self.opt.checksum_type);
         let mut buf = [0; FULL_FOOTER_LENGTH];
         footer.encode(&mut buf);
 
@@ -276,7 +282,11 @@ mod tests {
 
     #[test]
     fn test_footer() {
-        let f = Footer::new(BlockHandle::new(44, 4), BlockHandle::new(55, 5));
+        let f = Footer::new(
+            BlockHandle::new(44, 4),
+            BlockHandle::new(55, 5),
+            ChecksumType::Xxh3,
+        );
         let mut buf = [0; 48];
         f.encode(&mut buf[..]);
 
@@ -285,6 +295,13 @@ mod tests {
         assert_eq!(f2.meta_index.size(), 4);
         assert_eq!(f2.index.offset(), 55);
         assert_eq!(f2.index.size(), 5);
+        assert_eq!(f2.checksum, ChecksumType::Xxh3);
+
+        // Footers without a checksum type use CRC32C; unknown types are rejected.
+        buf[FOOTER_LENGTH - 1] = 0;
+        assert_eq!(Footer::decode(&buf).unwrap().checksum, ChecksumType::Crc32c);
+        buf[FOOTER_LENGTH - 1] = 0xff;
+        assert!(Footer::decode(&buf).is_none());
     }
 
     #[test]
@@ -3,14 +3,16 @@
 //! returned.
 
 use crate::cache::{self, Cache};
+use crate::env::RandomAccess;
 use crate::error::{err, Result, StatusCode};
-use crate::key_types::InternalKey;
+use crate::key_types::{parse_internal_key, InternalKey};
 use crate::options::Options;
-use crate::table_reader::Table;
-use crate::types::FileNum;
+use crate::table_reader::{KeyValue, Table};
+use crate::types::{FileMetaData, FileNum};
 
 use integer_encoding::FixedIntWriter;
 
+use std::cell::Cell;
 use std::convert::AsRef;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
@@ -26,10 +28,40 @@ fn filenum_to_key(num: FileNum) -> cache::CacheKey {
     buf
 }
 
+/// MappedFile counts the memory-mapped files of a TableCache that are still open, e.g. because
+/// an iterator uses a table that has been evicted already.
+struct MappedFile {
+    file: Box<dyn RandomAccess>,
+    open: Rc<Cell<usize>>,
+}
+
+impl MappedFile {
+    fn new(file: Box<dyn RandomAccess>, open: Rc<Cell<usize>>) -> MappedFile {
+        open.set(open.get() + 1);
+        MappedFile { file, open }
+    }
+}
+
+impl RandomAccess for MappedFile {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        self.file.read_at(off, dst)
+    }
+    fn read_batch(&self, reqs: &mut [(usize, &mut [u8])]) -> Result<Vec<usize>> {
+        self.file.read_batch(reqs)
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        self.open.set(self.open.get() - 1);
+    }
+}
+
 pub struct TableCache {
     dbname: PathBuf,
     cache: Cache<Table>,
     opts: Options,
+    mapped_files: Rc<Cell<usize>>,
 }
 
 impl TableCache {
@@ -41,18 +73,51 @@ impl TableCache {
             dbname: db.as_ref().to_owned(),
             cache: Cache::new(entries),
             opts: opt,
+            mapped_files: Rc::new(Cell::new(0)),
         }
     }
 
+    /// Returns whether errors reading tables should be treated as fatal.
+    pub fn paranoid_checks(&self) -> bool {
+        self.opts.paranoid_checks
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.opts
+    }
+
+    /// Look up `key` in the table `file` at `level`.
     pub fn get<'a>(
         &mut self,
-        file_num: FileNum,
+        file: &FileMetaData,
+        level: usize,
         key: InternalKey<'a>,
     ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
-        let tbl = self.get_table(file_num)?;
+        let tbl = self.get_file_table(file, level)?;
         tbl.get(key)
     }
 
+    /// Like `get()`, for several keys at once (see `Table::get_many()`).
+    pub fn get_many(
+        &mut self,
+        file: &FileMetaData,
+        level: usize,
+        keys: &[InternalKey],
+    ) -> Result<Vec<Option<KeyValue>>> {
+        let tbl = self.get_file_table(file, level)?;
+        tbl.get_many(keys)
+    }
+
+    /// Like `get_table_at_level()`, for the table described by `file`. Entries stored in it with
+    /// sequence number 0 are read with the sequence number of `file.largest`, which is the one
+    /// assigned to an ingested table (see `Table::with_global_seq()`).
+    pub fn get_file_table(&mut self, file: &FileMetaData, level: usize) -> Result<Table> {
+        let seq = parse_internal_key(&file.largest).1;
+        Ok(self
+            .get_table_at_level(file.num, level)?
+            .with_global_seq(seq))
+    }
+
     /// Return a table from cache, or open the backing file, then cache and return it.
     pub fn get_table(&mut self, file_num: FileNum) -> Result<Table> {
         let key = filenum_to_key(file_num);
@@ -62,6 +127,25 @@ impl TableCache {
         self.open_table(file_num)
     }
 
+    /// Like `get_table()`, for a table known to be at `level`. With
+    /// `Options::pin_l0_filter_and_index_blocks_in_cache`, the index and filter blocks of L0
+    /// tables are pinned in the block cache while the table is open and at L0.
+    pub fn get_table_at_level(&mut self, file_num: FileNum, level: usize) -> Result<Table> {
+        let table = self.get_table(file_num)?;
+        if level > 0 && table.has_cache_pins() {
+            // The table has been moved out of L0.
+            let table = table.unpinned();
+            self.cache.insert(&filenum_to_key(file_num), table.clone());
+            return Ok(table);
+        }
+        if level > 0 || !self.opts.pin_l0_filter_and_index_blocks_in_cache || table.is_pinned() {
+            return Ok(table);
+        }
+        let table = table.pin()?;
+        self.cache.insert(&filenum_to_key(file_num), table.clone());
+        Ok(table)
+    }
+
     /// Open a table on the file system and read it.
     fn open_table(&mut self, file_num: FileNum) -> Result<Table> {
         let name = table_file_name(&self.dbname, file_num);
@@ -70,13 +154,33 @@ impl TableCache {
         if file_size == 0 {
             return err(StatusCode::InvalidData, "file is empty");
         }
-        let file = Rc::new(self.opts.env.open_random_access_file(&path)?);
+        let mmap = self.opts.allow_mmap_reads && self.mapped_files.get() < self.opts.max_open_files;
+        let file: Box<dyn RandomAccess> = if mmap {
+            let f = self.opts.env.open_mmap_file(path)?;
+            Box::new(MappedFile::new(f, self.mapped_files.clone()))
+        } else if self.opts.use_direct_reads {
+            self.opts.env.open_direct_random_access_file(path)?
+        } else {
+            self.opts.env.open_random_access_file(path)?
+        };
+        let file = Rc::new(file);
         // No SSTable file name compatibility.
         let table = Table::new(self.opts.clone(), file, file_size)?;
         self.cache.insert(&filenum_to_key(file_num), table.clone());
         Ok(table)
     }
 
+    /// Stops pinning the index and filter blocks of a table that has been moved out of L0 (see
+    /// `get_table_at_level()`).
+    pub fn unpin(&mut self, file_num: FileNum) {
+        let key = filenum_to_key(file_num);
+        let unpinned = match self.cache.get(&key) {
+            Some(t) if t.has_cache_pins() => t.unpinned(),
+            _ => return,
+        };
+        self.cache.insert(&key, unpinned);
+    }
+
     pub fn evict(&mut self, file_num: FileNum) -> Result<()> {
         if self.cache.remove(&filenum_to_key(file_num)).is_some() {
             Ok(())
@@ -163,4 +267,56 @@ mod tests {
         assert!(cache.evict(123).is_err());
         assert!(cache.cache.get(&filenum_to_key(123)).is_none());
     }
+
+    #[test]
+    fn test_table_cache_mmap_limit() {
+        let mut opt = options::for_test();
+        opt.allow_mmap_reads = true;
+        opt.max_open_files = 1;
+        let dbname = Path::new("testdb1");
+        write_table_to(opt.clone(), &table_file_name(dbname, 1));
+        write_table_to(opt.clone(), &table_file_name(dbname, 2));
+
+        let mut cache = TableCache::new(dbname, opt, 10);
+        let t1 = cache.get_table(1).unwrap();
+        assert_eq!(1, cache.mapped_files.get());
+        // The second table is read normally.
+        let mut iter = cache.get_table(2).unwrap().iter();
+        assert_eq!(4, LdbIteratorIter::wrap(&mut iter).count());
+        assert_eq!(1, cache.mapped_files.get());
+
+        // The mapping is released once the table isn't used anymore.
+        cache.evict(1).unwrap();
+        assert_eq!(1, cache.mapped_files.get());
+        drop(t1);
+        assert_eq!(0, cache.mapped_files.get());
+    }
+
+    #[test]
+    fn test_table_cache_pin_l0() {
+        let mut opt = options::for_test();
+        opt.cache_index_and_filter_blocks = true;
+        opt.pin_l0_filter_and_index_blocks_in_cache = true;
+        let dbname = Path::new("testdb1");
+        write_table_to(opt.clone(), &table_file_name(dbname, 123));
+
+        let mut cache = TableCache::new(dbname, opt.clone(), 10);
+        assert!(!cache.get_table_at_level(123, 1).unwrap().is_pinned());
+        assert!(cache.get_table_at_level(123, 0).unwrap().is_pinned());
+        // The pinned table is cached.
+        assert!(cache.get_table(123).unwrap().is_pinned());
+        assert!(opt.block_cache.borrow().pinned_usage() > 0);
+
+        cache.evict(123).unwrap();
+        assert_eq!(0, opt.block_cache.borrow().pinned_usage());
+
+        // Tables moved out of L0 are unpinned.
+        cache.get_table_at_level(123, 0).unwrap();
+        assert!(!cache.get_table_at_level(123, 1).unwrap().is_pinned());
+        assert_eq!(0, opt.block_cache.borrow().pinned_usage());
+        cache.get_table_at_level(123, 0).unwrap();
+        cache.unpin(123);
+        assert!(!cache.get_table(123).unwrap().is_pinned());
+        assert_eq!(0, opt.block_cache.borrow().pinned_usage());
+    }
 }
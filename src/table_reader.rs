@@ -1,17 +1,20 @@
 use crate::block::{Block, BlockIter};
+use crate::block_builder::BlockBuilder;
 use crate::blockhandle::BlockHandle;
-use crate::cache;
+use crate::cache::{self, Cache, Priority};
 use crate::cmp::InternalKeyCmp;
 use crate::env::RandomAccess;
-use crate::error::{self, err, Result};
+use crate::error::{self, err, Result, Status};
 use crate::filter;
 use crate::filter_block::FilterBlockReader;
-use crate::key_types::InternalKey;
+use crate::key_types::{parse_internal_key, InternalKey};
 use crate::options::Options;
+use crate::statistics::{self, Ticker};
 use crate::table_block;
 use crate::table_builder::{self, Footer};
-use crate::types::{current_key_val, LdbIterator};
+use crate::types::{current_key_val, LdbIterator, SequenceNumber, Shared};
 
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
 use std::rc::Rc;
 
@@ -30,6 +33,33 @@ fn read_footer(f: &dyn RandomAccess, size: usize) -> Result<Footer> {
     }
 }
 
+/// A key and its value, as returned by `Table::get()`.
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// MetaBlock is the index or filter block of a table. It is either held by the table, or, with
+/// `Options::cache_index_and_filter_blocks`, looked up in the block cache whenever it is used.
+#[derive(Clone)]
+enum MetaBlock<T> {
+    Held(T),
+    Cached(BlockHandle),
+}
+
+/// CachePins unpins the index and filter blocks of a table once the last copy of the table has
+/// been dropped.
+struct CachePins {
+    cache: Shared<Cache<Block>>,
+    keys: Vec<cache::CacheKey>,
+}
+
+impl Drop for CachePins {
+    fn drop(&mut self) {
+        let mut cache = self.cache.borrow_mut();
+        for key in self.keys.iter() {
+            cache.unpin(key);
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Table {
     file: Rc<Box<dyn RandomAccess>>,
@@ -39,39 +69,61 @@ pub struct Table {
     opt: Options,
 
     footer: Footer,
-    indexblock: Block,
-    filters: Option<FilterBlockReader>,
+    indexblock: MetaBlock<Block>,
+    filters: Option<MetaBlock<FilterBlockReader>>,
+    pins: Option<Rc<CachePins>>,
+    // Replaces sequence number 0 in the keys read, if not 0 itself.
+    global_seq: SequenceNumber,
 }
 
 impl Table {
     /// Creates a new table reader operating on unformatted keys (i.e., UserKey).
     fn new_raw(opt: Options, file: Rc<Box<dyn RandomAccess>>, size: usize) -> Result<Table> {
         let footer = read_footer(file.as_ref().as_ref(), size)?;
-        let indexblock =
-            table_block::read_table_block(opt.clone(), file.as_ref().as_ref(), &footer.index)?;
-        let metaindexblock =
-            table_block::read_table_block(opt.clone(), file.as_ref().as_ref(), &footer.meta_index)?;
-
-        let filter_block_reader =
-            Table::read_filter_block(&metaindexblock, file.as_ref().as_ref(), &opt)?;
+        let metaindexblock = table_block::read_table_block(
+            opt.clone(),
+            file.as_ref().as_ref(),
+            &footer.meta_index,
+            footer.checksum,
+        )?;
+        let filter_location = Table::filter_block_location(&metaindexblock, &opt)?;
         let cache_id = opt.block_cache.borrow_mut().new_cache_id();
 
-        Ok(Table {
+        let mut table = Table {
             file,
             file_size: size,
             cache_id,
+            indexblock: MetaBlock::Cached(footer.index.clone()),
+            filters: filter_location.clone().map(MetaBlock::Cached),
             opt,
             footer,
-            filters: filter_block_reader,
-            indexblock,
-        })
+            pins: None,
+            global_seq: 0,
+        };
+        if table.opt.cache_index_and_filter_blocks {
+            // Load both blocks into the cache now, so that errors are detected early.
+            table.index_block()?;
+            table.filter_block()?;
+        } else {
+            let f = table.file.as_ref().as_ref();
+            table.indexblock = MetaBlock::Held(table_block::read_table_block(
+                table.opt.clone(),
+                f,
+                &table.footer.index,
+                table.footer.checksum,
+            )?);
+            if let Some(location) = filter_location {
+                let policy = table.opt.filter_policy.clone();
+                let filters = table_block::read_filter_block(f, &location, policy)?;
+                table.filters = Some(MetaBlock::Held(filters));
+            }
+        }
+        Ok(table)
     }
 
-    fn read_filter_block(
-        metaix: &Block,
-        file: &dyn RandomAccess,
-        options: &Options,
-    ) -> Result<Option<FilterBlockReader>> {
+    /// Returns the location of the filter block written by `options.filter_policy`, if there is
+    /// one.
+    fn filter_block_location(metaix: &Block, options: &Options) -> Result<Option<BlockHandle>> {
         // Open filter block for reading
         let filter_name = format!("filter.{}", options.filter_policy.name())
             .as_bytes()
@@ -80,7 +132,10 @@ impl Table {
         let mut metaindexiter = metaix.iter();
         metaindexiter.seek(&filter_name);
 
-        if let Some((_key, val)) = current_key_val(&metaindexiter) {
+        // Filters written by a different policy are not usable and therefore ignored.
+        if let Some((_, val)) =
+            current_key_val(&metaindexiter).filter(|(key, _)| *key == filter_name)
+        {
             let fbl = BlockHandle::decode(&val);
             let filter_block_location = match fbl {
                 None => {
@@ -92,11 +147,7 @@ impl Table {
                 Some(ok) => ok.0,
             };
             if filter_block_location.size() > 0 {
-                return Ok(Some(table_block::read_filter_block(
-                    file,
-                    &filter_block_location,
-                    options.filter_policy.clone(),
-                )?));
+                return Ok(Some(filter_block_location));
             }
         }
         Ok(None)
@@ -126,30 +177,216 @@ impl Table {
         dst
     }
 
-    /// Read a block from the current table at `location`, and cache it in the options' block
-    /// cache.
-    fn read_block(&self, location: &BlockHandle) -> Result<Block> {
+    /// Return the block at `location` from the options' block cache, or read it using `read` and
+    /// cache it with `priority`.
+    fn read_through_cache<F: FnOnce(&dyn RandomAccess) -> Result<Block>>(
+        &self,
+        location: &BlockHandle,
+        priority: Priority,
+        read: F,
+    ) -> Result<Block> {
         let cachekey = self.block_cache_handle(location.offset());
         if let Some(block) = self.opt.block_cache.borrow_mut().get(&cachekey) {
+            statistics::record_tick(&self.opt, Ticker::BlockCacheHit, 1);
             return Ok(block.clone());
         }
+        statistics::record_tick(&self.opt, Ticker::BlockCacheMiss, 1);
 
         // Two times as_ref(): First time to get a ref from Rc<>, then one from Box<>.
-        let b =
-            table_block::read_table_block(self.opt.clone(), self.file.as_ref().as_ref(), location)?;
+        let b = read(self.file.as_ref().as_ref())?;
+        self.insert_into_cache(location, &b, priority);
+        Ok(b)
+    }
 
-        // insert a cheap copy (Rc).
-        self.opt
-            .block_cache
-            .borrow_mut()
-            .insert(&cachekey, b.clone());
+    fn insert_into_cache(&self, location: &BlockHandle, b: &Block, priority: Priority) {
+        // insert a cheap copy (Rc), charged by the size of the block.
+        let charge = b.contents().len();
+        self.opt.block_cache.borrow_mut().insert_with_priority(
+            &self.block_cache_handle(location.offset()),
+            b.clone(),
+            charge,
+            priority,
+        );
+    }
 
-        Ok(b)
+    /// Read a block from the current table at `location`, and cache it in the options' block
+    /// cache.
+    fn read_block(&self, location: &BlockHandle) -> Result<Block> {
+        self.read_block_from(location, self.file.as_ref().as_ref())
+    }
+
+    /// Like `read_block()` for several blocks. The ones not in the block cache are read from the
+    /// table file in a single batch.
+    fn read_blocks(&self, locations: &[BlockHandle]) -> Result<Vec<Block>> {
+        let mut blocks = Vec::with_capacity(locations.len());
+        let mut missing = vec![];
+        for (i, location) in locations.iter().enumerate() {
+            let cachekey = self.block_cache_handle(location.offset());
+            let cached = self.opt.block_cache.borrow_mut().get(&cachekey).cloned();
+            if cached.is_some() {
+                statistics::record_tick(&self.opt, Ticker::BlockCacheHit, 1);
+            } else {
+                statistics::record_tick(&self.opt, Ticker::BlockCacheMiss, 1);
+                missing.push(i);
+            }
+            blocks.push(cached);
+        }
+
+        let mut bufs: Vec<Vec<u8>> = missing
+            .iter()
+            .map(|&i| vec![0; table_block::with_trailer(&locations[i]).size()])
+            .collect();
+        let mut reqs: Vec<(usize, &mut [u8])> = missing
+            .iter()
+            .zip(bufs.iter_mut())
+            .map(|(&i, buf)| (locations[i].offset(), buf.as_mut_slice()))
+            .collect();
+        let lens = self.file.read_batch(&mut reqs)?;
+
+        for ((i, buf), len) in missing.into_iter().zip(bufs).zip(lens) {
+            if len < buf.len() {
+                return err(
+                    error::StatusCode::Corruption,
+                    &format!(
+                        "short read of block at {}: {} of {} bytes",
+                        locations[i].offset(),
+                        len,
+                        buf.len()
+                    ),
+                );
+            }
+            let b = table_block::decode_table_block(
+                self.opt.clone(),
+                buf,
+                &locations[i],
+                self.footer.checksum,
+            )?;
+            self.insert_into_cache(&locations[i], &b, Priority::Low);
+            blocks[i] = Some(b);
+        }
+        Ok(blocks.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Like `read_block()`, but reading from `f` if the block isn't cached.
+    fn read_block_from(&self, location: &BlockHandle, f: &dyn RandomAccess) -> Result<Block> {
+        self.read_through_cache(location, Priority::Low, |_| {
+            table_block::read_table_block(self.opt.clone(), f, location, self.footer.checksum)
+        })
+    }
+
+    fn index_block(&self) -> Result<Block> {
+        match self.indexblock {
+            MetaBlock::Held(ref b) => Ok(b.clone()),
+            MetaBlock::Cached(ref location) => {
+                self.read_through_cache(location, Priority::High, |f| {
+                    table_block::read_table_block(
+                        self.opt.clone(),
+                        f,
+                        location,
+                        self.footer.checksum,
+                    )
+                })
+            }
+        }
+    }
+
+    fn filter_block(&self) -> Result<Option<FilterBlockReader>> {
+        match self.filters {
+            None => Ok(None),
+            Some(MetaBlock::Held(ref filters)) => Ok(Some(filters.clone())),
+            Some(MetaBlock::Cached(ref location)) => {
+                // Filter blocks are cached as Blocks, too, but never iterated over.
+                let b = self.read_through_cache(location, Priority::High, |f| {
+                    Ok(Block::new(
+                        self.opt.clone(),
+                        table_block::read_bytes(f, location)?,
+                    ))
+                })?;
+                let policy = self.opt.filter_policy.clone();
+                Ok(Some(FilterBlockReader::new(policy, b.contents())))
+            }
+        }
+    }
+
+    /// Returns true if the index and filter blocks of this table can't be evicted from memory:
+    /// Either because they are held by the table, or because they are pinned in the block cache.
+    pub fn is_pinned(&self) -> bool {
+        self.pins.is_some() || matches!(self.indexblock, MetaBlock::Held(_))
+    }
+
+    /// Returns a copy of this table whose index and filter blocks are pinned in the block cache
+    /// for as long as a copy of it exists.
+    pub fn pin(&self) -> Result<Table> {
+        if self.is_pinned() {
+            return Ok(self.clone());
+        }
+        let mut keys = vec![];
+        let mut pin = |location: &BlockHandle| {
+            let key = self.block_cache_handle(location.offset());
+            if self.opt.block_cache.borrow_mut().pin(&key) {
+                keys.push(key);
+            }
+        };
+        // Every block is pinned right after making sure that it is cached.
+        self.index_block()?;
+        pin(&self.footer.index);
+        if let Some(MetaBlock::Cached(ref location)) = self.filters {
+            self.filter_block()?;
+            pin(location);
+        }
+        let mut table = self.clone();
+        table.pins = Some(Rc::new(CachePins {
+            cache: self.opt.block_cache.clone(),
+            keys,
+        }));
+        Ok(table)
+    }
+
+    /// Returns a copy of this table that doesn't keep the blocks pinned by `pin()`. They are
+    /// unpinned once the pinned copies are gone.
+    pub fn unpinned(&self) -> Table {
+        let mut table = self.clone();
+        table.pins = None;
+        table
+    }
+
+    /// Returns true if this is a copy returned by `pin()`.
+    pub fn has_cache_pins(&self) -> bool {
+        self.pins.is_some()
+    }
+
+    /// Returns a copy of this table whose entries stored with sequence number 0, as written by
+    /// `SstFileWriter`, are read with sequence number `seq` instead. This is how ingested tables
+    /// get the sequence number assigned to them without being rewritten.
+    pub fn with_global_seq(&self, seq: SequenceNumber) -> Table {
+        let mut table = self.clone();
+        table.global_seq = seq;
+        table
+    }
+
+    /// Applies the global sequence number (see `with_global_seq()`) to an internal key read from
+    /// the table.
+    fn apply_global_seq(&self, key: &mut [u8]) {
+        if self.global_seq == 0 || key.len() < 8 {
+            return;
+        }
+        let (typ, seq, _) = parse_internal_key(key);
+        if seq == 0 {
+            let tag_off = key.len() - 8;
+            (&mut key[tag_off..])
+                .write_fixedint(self.global_seq << 8 | typ as u64)
+                .expect("write to slice failed");
+        }
     }
 
     /// Returns the offset of the block that contains `key`.
     pub fn approx_offset_of(&self, key: &[u8]) -> usize {
-        let mut iter = self.indexblock.iter();
+        let index = match self.index_block() {
+            Ok(index) => index,
+            // Without an index, the best guess is the end of the data.
+            Err(_) => return self.footer.meta_index.offset(),
+        };
+        let mut iter = index.iter();
 
         iter.seek(key);
 
@@ -163,11 +400,37 @@ impl Table {
 
     /// Iterators read from the file; thus only one iterator can be borrowed (mutably) per scope
     pub fn iter(&self) -> TableIterator {
+        self.new_iter(false)
+    }
+
+    /// Returns an iterator for reading the whole table, e.g. as a compaction input. It reads
+    /// ahead starting with the first block, instead of waiting for sequential access.
+    pub fn sequential_iter(&self) -> TableIterator {
+        self.new_iter(true)
+    }
+
+    fn new_iter(&self, sequential: bool) -> TableIterator {
+        // An iterator over a table whose index can't be read is empty. With paranoid_checks, it
+        // reports the error.
+        let (index_block, err) = match self.index_block() {
+            Ok(index) => (index.iter(), None),
+            Err(e) => {
+                let empty = BlockBuilder::new(self.opt.clone()).finish();
+                let err = if self.opt.paranoid_checks {
+                    Some(e)
+                } else {
+                    None
+                };
+                (Block::new(self.opt.clone(), empty).iter(), err)
+            }
+        };
         TableIterator {
             current_block: None,
             current_block_off: 0,
-            index_block: self.indexblock.iter(),
+            index_block,
+            readahead: Readahead::new(self, sequential),
             table: self.clone(),
+            err,
         }
     }
 
@@ -183,7 +446,42 @@ impl Table {
     /// exact match; it depends on other comparators than the one that the table reader knows
     /// whether a match is acceptable.
     pub fn get<'a>(&self, key: InternalKey<'a>) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
-        let mut index_iter = self.indexblock.iter();
+        match self.find_block(key)? {
+            // Read block (potentially from cache)
+            Some(handle) => Ok(self.get_from_block(&self.read_block(&handle)?, key)),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `get()`, for several keys at once. The data blocks that need to be read from the
+    /// table file are fetched in a single batch (see `RandomAccess::read_batch()`).
+    pub fn get_many(&self, keys: &[InternalKey]) -> Result<Vec<Option<KeyValue>>> {
+        let handles = keys
+            .iter()
+            .map(|key| self.find_block(key))
+            .collect::<Result<Vec<_>>>()?;
+        let mut locations: Vec<BlockHandle> = handles.iter().flatten().cloned().collect();
+        locations.sort_by_key(|h| h.offset());
+        locations.dedup_by_key(|h| h.offset());
+        let blocks = self.read_blocks(&locations)?;
+
+        Ok(keys
+            .iter()
+            .zip(handles)
+            .map(|(key, handle)| {
+                let handle = handle?;
+                let i = locations
+                    .binary_search_by_key(&handle.offset(), |h| h.offset())
+                    .unwrap();
+                self.get_from_block(&blocks[i], key)
+            })
+            .collect())
+    }
+
+    /// Returns the location of the block that may contain `key`, unless the index or the filter
+    /// block rule it out.
+    fn find_block(&self, key: InternalKey) -> Result<Option<BlockHandle>> {
+        let mut index_iter = self.index_block()?.iter();
         index_iter.seek(key);
 
         let handle;
@@ -200,24 +498,108 @@ impl Table {
         // found correct block.
 
         // Check bloom (or whatever) filter
-        if let Some(ref filters) = self.filters {
+        if let Some(filters) = self.filter_block()? {
             if !filters.key_may_match(handle.offset(), key) {
+                statistics::record_tick(&self.opt, Ticker::BloomFilterUseful, 1);
                 return Ok(None);
             }
+            statistics::record_tick(&self.opt, Ticker::BloomFilterPositive, 1);
         }
+        Ok(Some(handle))
+    }
 
-        // Read block (potentially from cache)
-        let tb = self.read_block(&handle)?;
-        let mut iter = tb.iter();
+    fn get_from_block(&self, block: &Block, key: InternalKey) -> Option<(Vec<u8>, Vec<u8>)> {
+        let mut iter = block.iter();
 
         // Go to entry and check if it's the wanted entry.
         iter.seek(key);
-        if let Some((k, v)) = current_key_val(&iter) {
+        while let Some((mut k, v)) = current_key_val(&iter) {
+            self.apply_global_seq(&mut k);
             if self.opt.cmp.cmp(&k, key) >= Ordering::Equal {
-                return Ok(Some((k, v)));
+                return Some((k, v));
             }
+            // The entry is too new after applying the global sequence number.
+            iter.advance();
         }
-        Ok(None)
+        None
+    }
+}
+
+/// The size of the first read ahead of an iterator. Every further one is twice as large, up to
+/// `Options::max_readahead_size`.
+const INITIAL_READAHEAD_SIZE: usize = 8 * 1024;
+/// The number of adjacent blocks an iterator reads before it starts reading ahead.
+const READAHEAD_TRIGGER: usize = 2;
+
+/// Readahead is the file of a TableIterator. Once the iterator reads adjacent blocks, every read
+/// from the table file also fetches the data following the requested block into a buffer, from
+/// which the next blocks are served.
+struct Readahead {
+    file: Rc<Box<dyn RandomAccess>>,
+    file_size: usize,
+    max_size: usize,
+    // The size of the next read ahead; 0 while the access isn't sequential.
+    size: Cell<usize>,
+    // The number of adjacent blocks accessed in a row, and the offset following the last one.
+    adjacent: usize,
+    next_offset: usize,
+    buf_offset: Cell<usize>,
+    buf: RefCell<Vec<u8>>,
+}
+
+impl Readahead {
+    fn new(table: &Table, sequential: bool) -> Readahead {
+        Readahead {
+            file: table.file.clone(),
+            file_size: table.file_size,
+            max_size: table.opt.max_readahead_size,
+            size: Cell::new(0),
+            // A sequential iterator starts reading ahead with the first block at offset 0.
+            adjacent: if sequential { READAHEAD_TRIGGER - 1 } else { 0 },
+            next_offset: if sequential { 0 } else { usize::MAX },
+            buf_offset: Cell::new(0),
+            buf: RefCell::new(vec![]),
+        }
+    }
+
+    /// Called before reading the block at `location`.
+    fn record_access(&mut self, location: &BlockHandle) {
+        if location.offset() == self.next_offset {
+            self.adjacent += 1;
+        } else {
+            self.adjacent = 1;
+            self.size.set(0);
+        }
+        self.next_offset = location.offset() + location.size() + table_block::BLOCK_TRAILER_LEN;
+        if self.adjacent >= READAHEAD_TRIGGER && self.size.get() == 0 {
+            self.size.set(INITIAL_READAHEAD_SIZE.min(self.max_size));
+        }
+    }
+}
+
+impl RandomAccess for Readahead {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        let mut buf = self.buf.borrow_mut();
+        let start = self.buf_offset.get();
+        if off >= start && off + dst.len() <= start + buf.len() {
+            dst.copy_from_slice(&buf[off - start..off - start + dst.len()]);
+            return Ok(dst.len());
+        }
+        let size = self.size.get();
+        if size == 0 {
+            return self.file.read_at(off, dst);
+        }
+
+        let len = dst.len().max(size.min(self.file_size.saturating_sub(off)));
+        buf.resize(len, 0);
+        let n = self.file.read_at(off, &mut buf)?;
+        buf.truncate(n);
+        self.buf_offset.set(off);
+        self.size.set((size * 2).min(self.max_size));
+
+        let n = n.min(dst.len());
+        dst[..n].copy_from_slice(&buf[..n]);
+        Ok(n)
     }
 }
 
@@ -234,6 +616,9 @@ pub struct TableIterator {
     current_block: Option<BlockIter>,
     current_block_off: usize,
     index_block: BlockIter,
+    readahead: Readahead,
+    // With paranoid_checks, the first error stops the iterator and is kept here.
+    err: Option<Status>,
 }
 
 impl TableIterator {
@@ -260,13 +645,30 @@ impl TableIterator {
             }
             Some(ok) => ok,
         };
-        let block = self.table.read_block(&new_block_handle)?;
+        self.readahead.record_access(&new_block_handle);
+        let block = match self
+            .table
+            .read_block_from(&new_block_handle, &self.readahead)
+        {
+            Ok(block) => block,
+            Err(e) => {
+                if self.table.opt.paranoid_checks && self.err.is_none() {
+                    self.err = Some(e.clone());
+                }
+                return Err(e);
+            }
+        };
 
         self.current_block = Some(block.iter());
         self.current_block_off = new_block_handle.offset();
 
         Ok(())
     }
+
+    // Returns true if the iterator has stopped because of an error.
+    fn failed(&self) -> bool {
+        self.err.is_some()
+    }
 }
 
 impl LdbIterator for TableIterator {
@@ -279,6 +681,10 @@ impl LdbIterator for TableIterator {
                     self.reset();
                     return false;
                 }
+                Err(_) if self.failed() => {
+                    self.reset();
+                    return false;
+                }
                 // try next block from index, this might be corruption
                 Err(_) => return self.advance(),
             }
@@ -299,6 +705,10 @@ impl LdbIterator for TableIterator {
                 self.reset();
                 false
             }
+            Err(_) if self.failed() => {
+                self.reset();
+                false
+            }
             // try next block, this might be corruption
             Err(_) => self.advance(),
         }
@@ -318,6 +728,14 @@ impl LdbIterator for TableIterator {
                 if let Ok(()) = self.load_block(&handle) {
                     // current_block is always set if load_block() returned Ok.
                     self.current_block.as_mut().unwrap().seek(to);
+                    // An entry may sort before `to` after applying the global sequence number.
+                    let (mut key, mut val) = (vec![], vec![]);
+                    if self.table.global_seq > 0
+                        && self.current(&mut key, &mut val)
+                        && self.table.opt.cmp.cmp(&key, to) == Ordering::Less
+                    {
+                        self.advance();
+                    }
                     return;
                 }
             }
@@ -365,21 +783,32 @@ impl LdbIterator for TableIterator {
 
     fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
         if let Some(ref cb) = self.current_block {
-            cb.current(key, val)
+            if !cb.current(key, val) {
+                return false;
+            }
+            self.table.apply_global_seq(key);
+            true
         } else {
             false
         }
     }
+
+    fn status(&self) -> Result<()> {
+        match self.err {
+            Some(ref e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::filter::BloomPolicy;
     use crate::key_types::LookupKey;
-    use crate::options::{self, CompressionType};
+    use crate::options::{self, ChecksumType, CompressionType};
     use crate::table_builder::TableBuilder;
     use crate::test_util::{test_iterator_properties, LdbIteratorIter};
-    use crate::types::{current_key_val, LdbIterator};
+    use crate::types::{current_key_val, share, LdbIterator};
 
     use super::*;
 
@@ -499,6 +928,101 @@ mod tests {
         assert_eq!(opt.block_cache.borrow().count(), 2);
     }
 
+    /// CountingFile counts the reads from a table; a batch counts as one read.
+    struct CountingFile(Vec<u8>, Rc<Cell<usize>>);
+
+    impl RandomAccess for CountingFile {
+        fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+            self.1.set(self.1.get() + 1);
+            self.0.read_at(off, dst)
+        }
+        fn read_batch(&self, reqs: &mut [(usize, &mut [u8])]) -> Result<Vec<usize>> {
+            self.1.set(self.1.get() + 1);
+            self.0.read_batch(reqs)
+        }
+    }
+
+    #[test]
+    fn test_table_readahead() {
+        let (src, size) = build_table(build_data());
+        let reads = Rc::new(Cell::new(0));
+        let file: Rc<Box<dyn RandomAccess>> = Rc::new(Box::new(CountingFile(src, reads.clone())));
+        let count_reads = |opt: Options, sequential: bool| {
+            let table = Table::new_raw(opt, file.clone(), size).unwrap();
+            reads.set(0);
+            let mut iter = if sequential {
+                table.sequential_iter()
+            } else {
+                table.iter()
+            };
+            assert_eq!(build_data().len(), LdbIteratorIter::wrap(&mut iter).count());
+            reads.get()
+        };
+
+        // Every block is read using one read.
+        let mut opt = options::for_test();
+        opt.max_readahead_size = 0;
+        let table = Table::new_raw(opt.clone(), file.clone(), size).unwrap();
+        let num_blocks = LdbIteratorIter::wrap(&mut table.index_block().unwrap().iter()).count();
+        assert_eq!(3, num_blocks);
+        assert_eq!(num_blocks, count_reads(opt, false));
+
+        // The second block is adjacent to the first one, and its read includes all others.
+        assert_eq!(2, count_reads(options::for_test(), false));
+        assert_eq!(1, count_reads(options::for_test(), true));
+
+        // Random access doesn't trigger reading ahead. The cache only holds one block.
+        let mut opt = options::for_test();
+        opt.block_cache = share(Cache::new(1));
+        let table = Table::new_raw(opt, file.clone(), size).unwrap();
+        reads.set(0);
+        let mut iter = table.iter();
+        for key in ["zzz", "abc", "zzz", "abc"] {
+            iter.seek(key.as_bytes());
+            assert!(iter.valid());
+        }
+        assert_eq!(4, reads.get());
+    }
+
+    #[test]
+    fn test_table_cache_index_and_filter_blocks() {
+        let (src, size) = build_table(build_data());
+        let mut opt = options::for_test();
+        opt.cache_index_and_filter_blocks = true;
+        opt.block_cache = share(Cache::new(1 << 20));
+        let cache = opt.block_cache.clone();
+
+        let table = Table::new_raw(opt, wrap_buffer(src), size).unwrap();
+        assert!(!table.is_pinned());
+        // The index and filter blocks are cached right away.
+        assert_eq!(2, cache.borrow().count());
+        let meta_usage = cache.borrow().usage();
+        let n = LdbIteratorIter::wrap(&mut table.iter()).count();
+        assert_eq!(build_data().len(), n);
+        assert!(cache.borrow().count() > 2);
+
+        // Data blocks are evicted first.
+        cache.borrow_mut().set_capacity(meta_usage);
+        assert_eq!(2, cache.borrow().count());
+        assert_eq!(meta_usage, cache.borrow().usage());
+
+        // Evicted index and filter blocks are read again.
+        cache.borrow_mut().set_capacity(1);
+        for (k, v) in build_data() {
+            let (fk, fv) = table.get(k.as_bytes()).unwrap().unwrap();
+            assert_eq!((k.as_bytes(), v.as_bytes()), (&fk[..], &fv[..]));
+        }
+
+        // Pinned blocks stay in the cache as long as the pinned table exists.
+        let pinned = table.pin().unwrap();
+        assert!(pinned.is_pinned());
+        assert_eq!(meta_usage, cache.borrow().pinned_usage());
+        table.get(b"zzz").unwrap();
+        assert_eq!(meta_usage, cache.borrow().pinned_usage());
+        drop(pinned);
+        assert_eq!(0, cache.borrow().count());
+    }
+
     #[test]
     fn test_table_iterator_fwd_bwd() {
         let (src, size) = build_table(build_data());
@@ -556,7 +1080,7 @@ mod tests {
 
         let table = Table::new_raw(options::for_test(), wrap_buffer(src), size).unwrap();
         assert!(table.filters.is_some());
-        let filter_reader = table.filters.clone().unwrap();
+        let filter_reader = table.filter_block().unwrap().unwrap();
         let mut iter = table.iter();
 
         loop {
@@ -697,6 +1221,52 @@ mod tests {
         assert!(table.get("zz{".as_bytes()).unwrap().is_none());
     }
 
+    #[test]
+    fn test_table_get_many() {
+        let (src, size) = build_table(build_data());
+        let reads = Rc::new(Cell::new(0));
+        let file: Rc<Box<dyn RandomAccess>> = Rc::new(Box::new(CountingFile(src, reads.clone())));
+        let table = Table::new_raw(options::for_test(), file, size).unwrap();
+
+        let mut keys: Vec<&[u8]> = vec![b"aaa", b"zzz", b"zz{"];
+        let data = build_data();
+        keys.extend(data.iter().rev().map(|(k, _)| k.as_bytes()));
+
+        reads.set(0);
+        let results = table.get_many(&keys).unwrap();
+        // All data blocks are read in one batch, and cached.
+        assert_eq!(1, reads.get());
+        assert_eq!(3, table.opt.block_cache.borrow().count());
+        for (key, result) in keys.iter().zip(results) {
+            assert_eq!(table.get(key).unwrap(), result);
+        }
+        assert_eq!(1, reads.get());
+        assert!(table.get_many(&keys[..1]).unwrap()[0].is_none());
+        assert!(table.get_many(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_table_get_many_short_read() {
+        // Batched reads come up one byte short.
+        struct ShortFile(Vec<u8>);
+        impl RandomAccess for ShortFile {
+            fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+                self.0.read_at(off, dst)
+            }
+            fn read_batch(&self, reqs: &mut [(usize, &mut [u8])]) -> Result<Vec<usize>> {
+                let lens = self.0.read_batch(reqs)?;
+                Ok(lens.into_iter().map(|n| n - 1).collect())
+            }
+        }
+
+        let (src, size) = build_table(build_data());
+        let file: Rc<Box<dyn RandomAccess>> = Rc::new(Box::new(ShortFile(src)));
+        let table = Table::new_raw(options::for_test(), file, size).unwrap();
+        let e = table.get_many(&[b"abc"]).err().unwrap();
+        assert_eq!(error::StatusCode::Corruption, e.code);
+        assert!(table.get(b"abc").unwrap().is_some());
+    }
+
     // This test verifies that the table and filters work with internal keys. This means:
     // The table contains keys in InternalKey format and it uses a filter wrapped by
     // InternalFilterPolicy.
@@ -709,7 +1279,7 @@ mod tests {
         let (src, size) = build_internal_table();
 
         let table = Table::new(options::for_test(), wrap_buffer(src), size).unwrap();
-        let filter_reader = table.filters.clone().unwrap();
+        let filter_reader = table.filter_block().unwrap().unwrap();
 
         // Check that we're actually using internal keys
         let mut _iter = table.iter();
@@ -738,6 +1308,77 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_table_global_seq() {
+        // A table as written by SstFileWriter, with sequence number 0 everywhere.
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(options::for_test(), &mut d);
+            for (k, v) in build_data() {
+                let key = LookupKey::new(k.as_bytes(), 0);
+                b.add(key.internal_key(), v.as_bytes()).unwrap();
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len();
+        let table = Table::new(options::for_test(), wrap_buffer(d), size).unwrap();
+        let ingested = table.with_global_seq(50);
+
+        let mut iter = ingested.iter();
+        let entries: Vec<_> = LdbIteratorIter::wrap(&mut iter)
+            .map(|(k, _)| {
+                let (_, seq, ukey) = parse_internal_key(&k);
+                (String::from_utf8(ukey.to_vec()).unwrap(), seq)
+            })
+            .collect();
+        let want: Vec<_> = build_data()
+            .into_iter()
+            .map(|(k, _)| (k.to_string(), 50))
+            .collect();
+        assert_eq!(want, entries);
+
+        let seq_of = |kv: Option<KeyValue>| {
+            let (k, _) = kv.unwrap();
+            let (_, seq, ukey) = parse_internal_key(&k);
+            (ukey.to_vec(), seq)
+        };
+        let visible = LookupKey::new(b"bsr", 100);
+        let too_old = LookupKey::new(b"bsr", 10);
+        assert_eq!(
+            (b"bsr".to_vec(), 50),
+            seq_of(ingested.get(visible.internal_key()).unwrap())
+        );
+        // The entry for bsr is newer than the lookup key, so the next one is returned.
+        assert_eq!(
+            (b"xyz".to_vec(), 50),
+            seq_of(ingested.get(too_old.internal_key()).unwrap())
+        );
+        assert_eq!(
+            (b"bsr".to_vec(), 0),
+            seq_of(table.get(too_old.internal_key()).unwrap())
+        );
+
+        let mut iter = ingested.iter();
+        iter.seek(visible.internal_key());
+        assert_eq!((b"bsr".to_vec(), 50), seq_of(current_key_val(&iter)));
+        iter.seek(too_old.internal_key());
+        assert_eq!((b"xyz".to_vec(), 50), seq_of(current_key_val(&iter)));
+    }
+
+    #[test]
+    fn test_table_reader_other_filter_policy() {
+        let (src, size) = build_table(build_data());
+        let mut opt = options::for_test();
+        opt.filter_policy = Rc::new(Box::new(filter::NoFilterPolicy::new()));
+
+        let table = Table::new_raw(opt, wrap_buffer(src), size).unwrap();
+        assert!(table.filters.is_none());
+        for (k, v) in build_data() {
+            let (fk, fv) = table.get(k.as_bytes()).unwrap().unwrap();
+            assert_eq!((k.as_bytes(), v.as_bytes()), (&fk[..], &fv[..]));
+        }
+    }
+
     #[test]
     fn test_table_reader_checksum() {
         let (mut src, size) = build_table(build_data());
@@ -747,7 +1388,7 @@ mod tests {
         let table = Table::new_raw(options::for_test(), wrap_buffer(src), size).unwrap();
 
         assert!(table.filters.is_some());
-        assert_eq!(table.filters.as_ref().unwrap().num(), 1);
+        assert_eq!(table.filter_block().unwrap().unwrap().num(), 1);
 
         {
             let mut _iter = table.iter();
@@ -769,4 +1410,46 @@ mod tests {
             panic!("Should have hit 5th record in table!");
         }
     }
+
+    #[test]
+    fn test_table_reader_xxh3_checksum() {
+        let mut src = vec![];
+        let mut opt = options::for_test();
+        opt.block_size = 32;
+        opt.checksum_type = ChecksumType::Xxh3;
+        let mut b = TableBuilder::new_raw(opt, &mut src);
+        for (k, v) in build_data() {
+            b.add(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+        let size = b.finish().unwrap();
+
+        let table = Table::new_raw(options::for_test(), wrap_buffer(src.clone()), size).unwrap();
+        assert_eq!(ChecksumType::Xxh3, table.footer.checksum);
+        let n = LdbIteratorIter::wrap(&mut table.iter()).count();
+        assert_eq!(build_data().len(), n);
+
+        // The first block is skipped once corrupted.
+        src[10] += 1;
+        let table = Table::new_raw(options::for_test(), wrap_buffer(src), size).unwrap();
+        let n = LdbIteratorIter::wrap(&mut table.iter()).count();
+        assert!(n < build_data().len());
+    }
+
+    #[test]
+    fn test_table_reader_checksum_paranoid() {
+        let (mut src, size) = build_table(build_data());
+        src[10] += 1;
+
+        let mut opt = options::for_test();
+        opt.paranoid_checks = true;
+        let table = Table::new_raw(opt, wrap_buffer(src), size).unwrap();
+
+        let mut iter = table.iter();
+        assert!(!iter.advance());
+        assert_eq!(
+            error::StatusCode::Corruption,
+            iter.status().err().unwrap().code
+        );
+        assert!(table.get(b"abc").is_err());
+    }
 }
@@ -80,6 +80,12 @@ pub trait LdbIterator {
         self.reset();
         self.advance();
     }
+
+    /// status returns the error that caused the iterator to stop, if any. Iterators that can't
+    /// encounter errors always return Ok.
+    fn status(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// current_key_val is a helper allocating two vectors and filling them with the current key/value
@@ -112,6 +118,9 @@ impl LdbIterator for Box<dyn LdbIterator> {
     fn prev(&mut self) -> bool {
         self.as_mut().prev()
     }
+    fn status(&self) -> Result<()> {
+        self.as_ref().status()
+    }
 }
 
 /// The unique (sequential) number of a file.
@@ -146,7 +155,7 @@ pub fn parse_file_name<P: AsRef<Path>>(ff: P) -> Result<(FileNum, FileType)> {
         return Ok((0, FileType::Current));
     } else if f == "LOCK" {
         return Ok((0, FileType::DBLock));
-    } else if f == "LOG" || f == "LOG.old" {
+    } else if f == "LOG" || f == "LOG.old" || f.starts_with("LOG.old.") {
         return Ok((0, FileType::InfoLog));
     } else if f.starts_with("MANIFEST-") {
         if let Some(ix) = f.find('-') {
@@ -194,6 +203,7 @@ mod tests {
             ("LOCK", (0, FileType::DBLock)),
             ("LOG", (0, FileType::InfoLog)),
             ("LOG.old", (0, FileType::InfoLog)),
+            ("LOG.old.1792326896789000", (0, FileType::InfoLog)),
             ("MANIFEST-01234", (1234, FileType::Descriptor)),
             ("001122.sst", (1122, FileType::Table)),
             ("001122.ldb", (1122, FileType::Table)),
@@ -0,0 +1,465 @@
+//! uring_env contains an Env submitting batched reads through io_uring, using the raw system
+//! calls. All other operations are those of PosixDiskEnv.
+
+use crate::disk_env::PosixDiskEnv;
+use crate::env::{Env, FileLock, Logger, RandomAccess};
+use crate::error::{Result, Status};
+use crate::types::{share, Shared};
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// The number of submission queue entries, i.e. the maximum number of reads per system call.
+const QUEUE_DEPTH: u32 = 64;
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_READ: u8 = 22;
+
+// The structures shared with the kernel, as defined in linux/io_uring.h.
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// Mapping is a memory region shared with the kernel.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Mapping> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.add(offset as usize) as *mut T }
+    }
+
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        unsafe { &*self.at::<AtomicU32>(offset) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+/// IoUring is a submission and a completion queue, which are only used for reads. As reads are
+/// waited for before returning, at most QUEUE_DEPTH operations are in flight at any time.
+struct IoUring {
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    params: Params,
+    // Set once a system call has failed; the ring isn't used anymore afterwards.
+    broken: bool,
+    // Declared last so that the queues are unmapped before the ring is closed.
+    fd: OwnedFd,
+}
+
+impl IoUring {
+    fn new(entries: u32) -> io::Result<IoUring> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let raw = fd.as_raw_fd();
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+        Ok(IoUring {
+            sq: Mapping::new(raw, sq_len, IORING_OFF_SQ_RING)?,
+            cq: Mapping::new(raw, cq_len, IORING_OFF_CQ_RING)?,
+            sqes: Mapping::new(raw, sqes_len, IORING_OFF_SQES)?,
+            params,
+            broken: false,
+            fd,
+        })
+    }
+
+    /// Read all of `reqs` from `fd`, submitting them together and waiting for their completion.
+    fn read(&mut self, fd: RawFd, reqs: &mut [(usize, &mut [u8])]) -> io::Result<Vec<usize>> {
+        let mut result = Vec::with_capacity(reqs.len());
+        for chunk in reqs.chunks_mut(self.params.sq_entries as usize) {
+            result.extend(self.read_chunk(fd, chunk)?);
+        }
+        Ok(result)
+    }
+
+    fn read_chunk(&mut self, fd: RawFd, reqs: &mut [(usize, &mut [u8])]) -> io::Result<Vec<usize>> {
+        let sq_off = &self.params.sq_off;
+        let mask = unsafe { *self.sq.at::<u32>(sq_off.ring_mask) };
+        let array = self.sq.at::<u32>(sq_off.array);
+        let sq_tail = self.sq.atomic(sq_off.tail);
+
+        let mut tail = sq_tail.load(Ordering::Relaxed);
+        for (i, (off, dst)) in reqs.iter_mut().enumerate() {
+            let index = tail & mask;
+            let sqe = Sqe {
+                opcode: IORING_OP_READ,
+                fd,
+                off: *off as u64,
+                addr: dst.as_mut_ptr() as u64,
+                len: dst.len() as u32,
+                user_data: i as u64,
+                ..Sqe::default()
+            };
+            unsafe {
+                ptr::write(self.sqes.at::<Sqe>(0).add(index as usize), sqe);
+                *array.add(index as usize) = index;
+            }
+            tail = tail.wrapping_add(1);
+        }
+        sq_tail.store(tail, Ordering::Release);
+
+        // The kernel writes into the buffers of `reqs` until the reads have completed, so all
+        // submitted reads are waited for before returning, even after an error.
+        let mut result = vec![0; reqs.len()];
+        let mut error = None;
+        let mut broken = false;
+        let mut submitted = 0;
+        let mut completed = 0;
+        while completed < submitted || (error.is_none() && submitted < reqs.len()) {
+            let to_submit = if error.is_none() {
+                reqs.len() - submitted
+            } else {
+                0
+            };
+            let r = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd(),
+                    to_submit,
+                    1u32,
+                    IORING_ENTER_GETEVENTS,
+                    ptr::null::<libc::sigset_t>(),
+                    0usize,
+                )
+            };
+            if r < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted && error.is_none() {
+                    error = Some(e);
+                    broken = true;
+                }
+                continue;
+            }
+            submitted += r as usize;
+
+            let cq_off = &self.params.cq_off;
+            let mask = unsafe { *self.cq.at::<u32>(cq_off.ring_mask) };
+            let cqes = self.cq.at::<Cqe>(cq_off.cqes);
+            let cq_head = self.cq.atomic(cq_off.head);
+            let mut head = cq_head.load(Ordering::Relaxed);
+            let tail = self.cq.atomic(cq_off.tail).load(Ordering::Acquire);
+            while head != tail {
+                let cqe = unsafe { &*cqes.add((head & mask) as usize) };
+                head = head.wrapping_add(1);
+                match result.get_mut(cqe.user_data as usize) {
+                    // Not one of our reads.
+                    None => {
+                        broken = true;
+                        error.get_or_insert(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "unexpected io_uring completion",
+                        ));
+                        continue;
+                    }
+                    Some(_) if cqe.res < 0 => {
+                        error.get_or_insert(io::Error::from_raw_os_error(-cqe.res));
+                    }
+                    Some(len) => *len = cqe.res as usize,
+                }
+                completed += 1;
+            }
+            cq_head.store(head, Ordering::Release);
+        }
+        if submitted < reqs.len() {
+            // The entries that weren't submitted must not be picked up later, when their buffers
+            // are gone: Retract them.
+            let sq_head = self.sq.atomic(sq_off.head).load(Ordering::Acquire);
+            sq_tail.store(sq_head, Ordering::Release);
+        }
+        self.broken |= broken;
+        match error {
+            Some(e) => Err(e),
+            None => Ok(result),
+        }
+    }
+}
+
+/// UringFile is a file whose batched reads are submitted to the io_uring of its Env.
+struct UringFile {
+    f: File,
+    ring: Shared<IoUring>,
+}
+
+impl RandomAccess for UringFile {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        self.f.read_at(off, dst)
+    }
+    fn read_batch(&self, reqs: &mut [(usize, &mut [u8])]) -> Result<Vec<usize>> {
+        let mut ring = self.ring.borrow_mut();
+        if ring.broken {
+            return reqs
+                .iter_mut()
+                .map(|(off, dst)| self.read_at(*off, dst))
+                .collect();
+        }
+        Ok(ring.read(self.f.as_raw_fd(), reqs)?)
+    }
+}
+
+/// UringEnv is a PosixDiskEnv whose random access files submit batched reads (see
+/// `RandomAccess::read_batch()`), like those of `DB::multi_get()`, with a single io_uring system
+/// call. Where io_uring isn't
+/// available, e.g. on kernels older than 5.6 or if it is forbidden by a seccomp filter, it
+/// behaves exactly like PosixDiskEnv.
+pub struct UringEnv {
+    env: PosixDiskEnv,
+    ring: Option<Shared<IoUring>>,
+}
+
+impl UringEnv {
+    pub fn new() -> UringEnv {
+        UringEnv {
+            env: PosixDiskEnv::new(),
+            ring: IoUring::new(QUEUE_DEPTH).ok().map(share),
+        }
+    }
+
+    /// Returns whether batched reads are submitted to io_uring.
+    pub fn uses_io_uring(&self) -> bool {
+        self.ring.is_some()
+    }
+}
+
+impl Default for UringEnv {
+    fn default() -> UringEnv {
+        UringEnv::new()
+    }
+}
+
+impl Env for UringEnv {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+        self.env.open_sequential_file(p)
+    }
+    fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        match self.ring {
+            Some(ref ring) => Ok(Box::new(UringFile {
+                f: File::open(p).map_err(|e| {
+                    let mut s = Status::from(e);
+                    s.err = format!("open (uring): {}: {}", s.err, p.display());
+                    s
+                })?,
+                ring: ring.clone(),
+            })),
+            None => self.env.open_random_access_file(p),
+        }
+    }
+    fn open_mmap_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        self.env.open_mmap_file(p)
+    }
+    fn open_direct_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        self.env.open_direct_random_access_file(p)
+    }
+    fn open_direct_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        self.env.open_direct_writable_file(p)
+    }
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        self.env.open_writable_file(p)
+    }
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        self.env.open_appendable_file(p)
+    }
+
+    fn exists(&self, p: &Path) -> Result<bool> {
+        self.env.exists(p)
+    }
+    fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        self.env.children(p)
+    }
+    fn size_of(&self, p: &Path) -> Result<usize> {
+        self.env.size_of(p)
+    }
+    fn modified_micros(&self, p: &Path) -> Result<u64> {
+        self.env.modified_micros(p)
+    }
+
+    fn delete(&self, p: &Path) -> Result<()> {
+        self.env.delete(p)
+    }
+    fn mkdir(&self, p: &Path) -> Result<()> {
+        self.env.mkdir(p)
+    }
+    fn rmdir(&self, p: &Path) -> Result<()> {
+        self.env.rmdir(p)
+    }
+    fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        self.env.rename(old, new)
+    }
+    fn link(&self, old: &Path, new: &Path) -> Result<()> {
+        self.env.link(old, new)
+    }
+
+    fn lock(&self, p: &Path) -> Result<FileLock> {
+        self.env.lock(p)
+    }
+    fn unlock(&self, l: FileLock) -> Result<()> {
+        self.env.unlock(l)
+    }
+
+    fn new_logger(&self, p: &Path) -> Result<Logger> {
+        self.env.new_logger(p)
+    }
+
+    fn micros(&self) -> u64 {
+        self.env.micros()
+    }
+    fn sleep_for(&self, micros: u32) {
+        self.env.sleep_for(micros)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn test_uring_env_read_batch() {
+        let env = UringEnv::new();
+        let name = Path::new("testfile.uring");
+        let data: Vec<u8> = (0..100000).map(|i| i as u8).collect();
+        env.open_writable_file(name)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+
+        let f = env.open_random_access_file(name).unwrap();
+        // More reads than fit into the submission queue at once.
+        let mut bufs: Vec<Vec<u8>> = (0..QUEUE_DEPTH as usize * 2 + 1)
+            .map(|i| vec![0; i + 1])
+            .collect();
+        let mut reqs: Vec<(usize, &mut [u8])> = bufs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, b)| (i * 700, b.as_mut_slice()))
+            .collect();
+        let mut last = [0; 10];
+        reqs.push((data.len() - 3, &mut last));
+        let lens = f.read_batch(&mut reqs).unwrap();
+
+        assert_eq!(reqs.len(), lens.len());
+        for (i, (off, dst)) in reqs.iter().enumerate().take(reqs.len() - 1) {
+            assert_eq!(i + 1, lens[i]);
+            assert_eq!(&data[*off..*off + i + 1], &dst[..]);
+        }
+        assert_eq!(3, lens[reqs.len() - 1]);
+        assert_eq!(&data[data.len() - 3..], &reqs[reqs.len() - 1].1[..3]);
+
+        fs::remove_file(name).unwrap();
+    }
+}
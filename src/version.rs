@@ -1,11 +1,13 @@
 use crate::cmp::{Cmp, InternalKeyCmp};
-use crate::error::Result;
+use crate::error::{Result, Status};
 use crate::key_types::{parse_internal_key, InternalKey, LookupKey, UserKey, ValueType};
+use crate::options::{CompactionStyle, Options};
 use crate::table_cache::TableCache;
 use crate::table_reader::TableIterator;
 use crate::types::{FileMetaData, FileNum, LdbIterator, Shared, MAX_SEQUENCE_NUMBER, NUM_LEVELS};
 
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
 use std::default::Default;
 use std::rc::Rc;
 
@@ -82,24 +84,102 @@ impl Version {
                 // We receive both key and value from the table. Because we're using InternalKey
                 // keys, we now need to check whether the found entry's user key is equal to the
                 // one we're looking for (get() just returns the next-bigger key).
-                if let Ok(Some((k, v))) = self.table_cache.borrow_mut().get(f.borrow().num, ikey) {
-                    // We don't need to check the sequence number; get() will not return an entry
-                    // with a higher sequence number than the one in the supplied key.
-                    let (typ, _, foundkey) = parse_internal_key(&k);
-                    if typ == ValueType::TypeValue
-                        && self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal
-                    {
-                        return Ok(Some((v, stats)));
-                    } else if typ == ValueType::TypeDeletion {
-                        // Skip looking once we have found a deletion.
-                        return Ok(None);
+                let found = self.table_cache.borrow_mut().get(&f.borrow(), level, ikey);
+                match found {
+                    Ok(Some((k, v))) => {
+                        // We don't need to check the sequence number; get() will not return an
+                        // entry with a higher sequence number than the one in the supplied key.
+                        let (typ, _, foundkey) = parse_internal_key(&k);
+                        if typ == ValueType::TypeValue
+                            && self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal
+                        {
+                            if let Some(ref s) = self.table_cache.borrow().options().statistics {
+                                s.borrow_mut().record_found_in_level(level);
+                            }
+                            return Ok(Some((v, stats)));
+                        } else if typ == ValueType::TypeDeletion {
+                            // Skip looking once we have found a deletion.
+                            return Ok(None);
+                        }
                     }
+                    Ok(None) => {}
+                    // Without paranoid checks, unreadable tables are skipped.
+                    Err(e) if self.table_cache.borrow().paranoid_checks() => return Err(e),
+                    Err(_) => {}
                 }
             }
         }
         Ok(None)
     }
 
+    /// get_many is like get(), for several keys at once. Every table is asked for all keys that
+    /// need to be looked up in it together, so that the data blocks they are in are read in one
+    /// batch (see `Table::get_many()`). Unlike get(), it doesn't charge seeks to the tables.
+    pub fn get_many(&self, keys: &[InternalKey]) -> Result<Vec<Option<Vec<u8>>>> {
+        // The tables each key remains to be looked up in, in order.
+        let mut pending: Vec<VecDeque<(usize, FileMetaHandle)>> = keys
+            .iter()
+            .map(|key| {
+                let levels = self.get_overlapping(key);
+                let mut files = VecDeque::new();
+                for (level, fs) in levels.iter().enumerate() {
+                    files.extend(fs.iter().map(|f| (level, f.clone())));
+                }
+                files
+            })
+            .collect();
+        let mut results = vec![None; keys.len()];
+
+        loop {
+            // The keys to look up next, by table.
+            let mut next: BTreeMap<FileNum, (usize, FileMetaHandle, Vec<usize>)> = BTreeMap::new();
+            for (i, files) in pending.iter().enumerate() {
+                if let Some((level, f)) = files.front() {
+                    next.entry(f.borrow().num)
+                        .or_insert((*level, f.clone(), vec![]))
+                        .2
+                        .push(i);
+                }
+            }
+            if next.is_empty() {
+                return Ok(results);
+            }
+
+            for (_, (level, f, ixs)) in next {
+                let lookup: Vec<InternalKey> = ixs.iter().map(|&i| keys[i]).collect();
+                let found = self
+                    .table_cache
+                    .borrow_mut()
+                    .get_many(&f.borrow(), level, &lookup);
+                let found = match found {
+                    Ok(found) => found,
+                    Err(e) if self.table_cache.borrow().paranoid_checks() => return Err(e),
+                    // Without paranoid checks, unreadable tables are skipped.
+                    Err(_) => vec![None; ixs.len()],
+                };
+                for (i, entry) in ixs.into_iter().zip(found) {
+                    pending[i].pop_front();
+                    if let Some((k, v)) = entry {
+                        // See get() on why the user key and type are checked.
+                        let (typ, _, foundkey) = parse_internal_key(&k);
+                        let ukey = parse_internal_key(keys[i]).2;
+                        if typ == ValueType::TypeValue
+                            && self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal
+                        {
+                            if let Some(ref s) = self.table_cache.borrow().options().statistics {
+                                s.borrow_mut().record_found_in_level(level);
+                            }
+                            results[i] = Some(v);
+                            pending[i].clear();
+                        } else if typ == ValueType::TypeDeletion {
+                            pending[i].clear();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     /// get_overlapping returns the files overlapping key in each level.
     fn get_overlapping<'a>(&self, key: InternalKey<'a>) -> [Vec<FileMetaHandle>; NUM_LEVELS] {
         let mut levels: [Vec<FileMetaHandle>; NUM_LEVELS] = Default::default();
@@ -162,16 +242,24 @@ impl Version {
         acc
     }
 
-    pub fn pick_memtable_output_level<'a, 'b>(&self, min: UserKey<'a>, max: UserKey<'b>) -> usize {
+    pub fn pick_memtable_output_level<'a, 'b>(
+        &self,
+        opt: &Options,
+        min: UserKey<'a>,
+        max: UserKey<'b>,
+    ) -> usize {
         let mut level = 0;
+        if opt.compaction_style == CompactionStyle::Universal {
+            // All tables are kept in L0.
+            return 0;
+        }
         if !self.overlap_in_level(0, min, max) {
             // Go to next level as long as there is no overlap in that level and a limited overlap
             // in the next-higher level.
             let start = LookupKey::new(min, MAX_SEQUENCE_NUMBER);
             let limit = LookupKey::new_full(max, 0, ValueType::TypeDeletion);
 
-            const MAX_MEM_COMPACT_LEVEL: usize = 2;
-            while level < MAX_MEM_COMPACT_LEVEL {
+            while level < opt.max_mem_compaction_level {
                 if self.overlap_in_level(level + 1, min, max) {
                     break;
                 }
@@ -182,7 +270,7 @@ impl Version {
                         &limit.internal_key(),
                     );
                     let size = total_size(overlaps.iter());
-                    if size > 10 * (2 << 20) {
+                    if size > 10 * opt.max_file_size_for_level(level + 2) {
                         break;
                     }
                 }
@@ -192,6 +280,25 @@ impl Version {
         level
     }
 
+    /// pick_ingestion_level returns the deepest level at which a table with the user key range
+    /// [min; max] can be placed without overlapping any file in that level or the levels above.
+    pub fn pick_ingestion_level<'a, 'b>(&self, min: UserKey<'a>, max: UserKey<'b>) -> usize {
+        let start = LookupKey::new(min, MAX_SEQUENCE_NUMBER);
+        let limit = LookupKey::new_full(max, 0, ValueType::TypeDeletion);
+
+        let mut level = 0;
+        for l in 0..NUM_LEVELS {
+            if !self
+                .overlapping_inputs(l, start.internal_key(), limit.internal_key())
+                .is_empty()
+            {
+                break;
+            }
+            level = l;
+        }
+        level
+    }
+
     /// record_read_sample returns true if there is a new file to be compacted. It counts the
     /// number of files overlapping a key, and which level contains the first overlap.
     #[allow(unused_assignments)]
@@ -351,6 +458,7 @@ impl Version {
     fn new_concat_iter(&self, level: usize) -> VersionIter {
         new_version_iter(
             self.files[level].clone(),
+            level,
             self.table_cache.clone(),
             self.user_cmp.clone(),
         )
@@ -364,7 +472,7 @@ impl Version {
             iters.push(Box::new(
                 self.table_cache
                     .borrow_mut()
-                    .get_table(f.borrow().num)?
+                    .get_file_table(&f.borrow(), 0)?
                     .iter(),
             ));
         }
@@ -380,18 +488,22 @@ impl Version {
 }
 
 /// new_version_iter returns an iterator over the entries in the specified ordered list of table
-/// files.
+/// files at `level`.
 pub fn new_version_iter(
     files: Vec<FileMetaHandle>,
+    level: usize,
     cache: Shared<TableCache>,
     ucmp: Rc<Box<dyn Cmp>>,
 ) -> VersionIter {
     VersionIter {
         files,
+        level,
         cache,
         cmp: InternalKeyCmp(ucmp),
         current: None,
         current_ix: 0,
+        err: None,
+        sequential: false,
     }
 }
 
@@ -403,11 +515,32 @@ pub struct VersionIter {
     // NOTE: Maybe we need to change this to Rc to support modification of the file set after
     // creation of the iterator. Versions should be immutable, though.
     files: Vec<FileMetaHandle>,
+    level: usize,
     cache: Shared<TableCache>,
     cmp: InternalKeyCmp,
 
     current: Option<TableIterator>,
     current_ix: usize,
+    err: Option<Status>,
+    // Whether tables are read using Table::sequential_iter() when advancing.
+    sequential: bool,
+}
+
+impl VersionIter {
+    /// Read ahead in every table right from the start, for iterators that read all entries, like
+    /// compaction inputs.
+    pub fn sequential(mut self) -> VersionIter {
+        self.sequential = true;
+        self
+    }
+
+    // With paranoid_checks, an error stops the iterator and is kept; otherwise, the iterator just
+    // ends, as if the table were empty.
+    fn record_error(&mut self, e: Status) {
+        if self.cache.borrow().paranoid_checks() {
+            self.err = Some(e);
+        }
+    }
 }
 
 impl LdbIterator for VersionIter {
@@ -417,6 +550,9 @@ impl LdbIterator for VersionIter {
         if let Some(ref mut t) = self.current {
             if t.advance() {
                 return true;
+            } else if let Err(e) = t.status() {
+                self.err = Some(e);
+                return false;
             } else if self.current_ix >= self.files.len() - 1 {
                 // Already on last table; can't advance further.
                 return false;
@@ -427,14 +563,17 @@ impl LdbIterator for VersionIter {
         }
 
         // Initialize iterator or load next table.
-        if let Ok(tbl) = self
+        let tbl = self
             .cache
             .borrow_mut()
-            .get_table(self.files[self.current_ix].borrow().num)
-        {
-            self.current = Some(tbl.iter());
-        } else {
-            return false;
+            .get_file_table(&self.files[self.current_ix].borrow(), self.level);
+        match tbl {
+            Ok(tbl) if self.sequential => self.current = Some(tbl.sequential_iter()),
+            Ok(tbl) => self.current = Some(tbl.iter()),
+            Err(e) => {
+                self.record_error(e);
+                return false;
+            }
         }
         self.advance()
     }
@@ -447,18 +586,24 @@ impl LdbIterator for VersionIter {
     }
     fn seek(&mut self, key: &[u8]) {
         if let Some(ix) = find_file(&self.cmp, &self.files, key) {
-            if let Ok(tbl) = self
+            let tbl = self
                 .cache
                 .borrow_mut()
-                .get_table(self.files[ix].borrow().num)
-            {
-                let mut iter = tbl.iter();
-                iter.seek(key);
-                if iter.valid() {
-                    self.current_ix = ix;
-                    self.current = Some(iter);
-                    return;
+                .get_file_table(&self.files[ix].borrow(), self.level);
+            match tbl {
+                Ok(tbl) => {
+                    let mut iter = tbl.iter();
+                    iter.seek(key);
+                    if iter.valid() {
+                        self.current_ix = ix;
+                        self.current = Some(iter);
+                        return;
+                    }
+                    if let Err(e) = iter.status() {
+                        self.record_error(e);
+                    }
                 }
+                Err(e) => self.record_error(e),
             }
         }
         self.reset();
@@ -477,7 +622,11 @@ impl LdbIterator for VersionIter {
             } else if self.current_ix > 0 {
                 let f = &self.files[self.current_ix - 1];
                 // Find previous table, seek to last entry.
-                if let Ok(tbl) = self.cache.borrow_mut().get_table(f.borrow().num) {
+                let tbl = self
+                    .cache
+                    .borrow_mut()
+                    .get_file_table(&f.borrow(), self.level);
+                if let Ok(tbl) = tbl {
                     let mut iter = tbl.iter();
                     iter.seek(&f.borrow().largest);
                     // The saved largest key must be in the table.
@@ -491,6 +640,12 @@ impl LdbIterator for VersionIter {
         self.reset();
         false
     }
+    fn status(&self) -> Result<()> {
+        if let Some(ref e) = self.err {
+            return Err(e.clone());
+        }
+        self.current.as_ref().map(|t| t.status()).unwrap_or(Ok(()))
+    }
 }
 
 /// total_size returns the sum of sizes of the given files.
@@ -853,7 +1008,7 @@ mod tests {
 
     #[test]
     fn test_version_pick_memtable_output_level() {
-        let v = make_version().0;
+        let (v, mut opt) = make_version();
 
         for c in [
             ("000".as_bytes(), "abc".as_bytes(), 0),
@@ -862,7 +1017,30 @@ mod tests {
         ]
         .iter()
         {
-            assert_eq!(c.2, v.pick_memtable_output_level(c.0, c.1));
+            assert_eq!(c.2, v.pick_memtable_output_level(&opt, c.0, c.1));
+        }
+
+        opt.max_mem_compaction_level = 1;
+        assert_eq!(1, v.pick_memtable_output_level(&opt, b"000", b"111"));
+        opt.max_mem_compaction_level = 0;
+        assert_eq!(0, v.pick_memtable_output_level(&opt, b"000", b"111"));
+    }
+
+    #[test]
+    fn test_version_pick_ingestion_level() {
+        let v = make_version().0;
+
+        for c in [
+            ("000".as_bytes(), "111".as_bytes(), 6),
+            ("aab".as_bytes(), "aab".as_bytes(), 0),
+            ("cac".as_bytes(), "cad".as_bytes(), 0),
+            ("gac".as_bytes(), "gad".as_bytes(), 1),
+            ("hab".as_bytes(), "hac".as_bytes(), 2),
+            ("xxx".as_bytes(), "yyy".as_bytes(), 6),
+        ]
+        .iter()
+        {
+            assert_eq!(c.2, v.pick_ingestion_level(c.0, c.1));
         }
     }
 
@@ -100,6 +100,10 @@ impl VersionEdit {
         self.comparator = Some(name.to_string())
     }
 
+    pub fn comparator_name(&self) -> Option<&str> {
+        self.comparator.as_deref()
+    }
+
     pub fn set_log_num(&mut self, num: u64) {
         self.log_number = Some(num)
     }
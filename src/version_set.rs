@@ -4,7 +4,7 @@ use crate::error::{err, Result, Status, StatusCode};
 use crate::key_types::{parse_internal_key, InternalKey, UserKey};
 use crate::log::{LogReader, LogWriter};
 use crate::merging_iter::MergingIter;
-use crate::options::Options;
+use crate::options::{CompactionStyle, Options};
 use crate::table_cache::TableCache;
 use crate::types::{
     parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, Shared, NUM_LEVELS,
@@ -20,6 +20,9 @@ use std::rc::Rc;
 
 pub struct Compaction {
     level: usize,
+    output_level: usize,
+    // If outputs stay in the input level: whether the oldest tables of the level are included.
+    bottommost: bool,
     max_file_size: usize,
     input_version: Option<Shared<Version>>,
     level_ixs: [usize; NUM_LEVELS],
@@ -43,7 +46,9 @@ impl Compaction {
     pub fn new(opt: &Options, level: usize, input: Option<Shared<Version>>) -> Compaction {
         Compaction {
             level,
-            max_file_size: opt.max_file_size,
+            output_level: level + 1,
+            bottommost: false,
+            max_file_size: opt.max_file_size_for_level(level + 1),
             input_version: input,
             level_ixs: Default::default(),
             cmp: opt.cmp.clone(),
@@ -68,6 +73,16 @@ impl Compaction {
         self.level
     }
 
+    /// Returns the level that the compaction's outputs are written to.
+    pub fn output_level(&self) -> usize {
+        self.output_level
+    }
+
+    /// Returns the target size of the tables written by this compaction.
+    pub fn max_output_file_size(&self) -> usize {
+        self.max_file_size
+    }
+
     pub fn input(&self, parent: usize, ix: usize) -> FileMetaData {
         assert!(parent < 2);
         assert!(ix < self.inputs[parent].len());
@@ -101,8 +116,12 @@ impl Compaction {
     /// to contain the key.
     pub fn is_base_level_for<'a>(&mut self, k: UserKey<'a>) -> bool {
         assert!(self.input_version.is_some());
+        if self.output_level == self.level {
+            // Tables in this level that are not part of the compaction may contain the key.
+            return self.bottommost;
+        }
         let inp_version = self.input_version.as_ref().unwrap();
-        for level in self.level + 2..NUM_LEVELS {
+        for level in self.output_level + 1..NUM_LEVELS {
             let files = &inp_version.borrow().files[level];
             while self.level_ixs[level] < files.len() {
                 let f = files[self.level_ixs[level]].borrow();
@@ -121,7 +140,7 @@ impl Compaction {
     }
 
     pub fn is_trivial_move(&self) -> bool {
-        if self.manual {
+        if self.manual || self.output_level == self.level {
             return false;
         }
 
@@ -180,6 +199,9 @@ pub struct VersionSet {
     compaction_ptrs: [Vec<u8>; NUM_LEVELS],
 
     descriptor_log: Option<LogWriter<Box<dyn Write>>>,
+    // The manifest read by recover() and the number of records it contained. Used for following
+    // another instance's manifest in read-only mode.
+    manifest_tail: Option<(PathBuf, usize)>,
 }
 
 impl VersionSet {
@@ -202,6 +224,7 @@ impl VersionSet {
             current: Some(v),
             compaction_ptrs: Default::default(),
             descriptor_log: None,
+            manifest_tail: None,
         }
     }
 
@@ -224,6 +247,10 @@ impl VersionSet {
 
     /// current returns a reference to the current version. It panics if there is no current
     /// version.
+    pub fn options(&self) -> &Options {
+        &self.opt
+    }
+
     pub fn current(&self) -> Shared<Version> {
         assert!(self.current.is_some());
         self.current.as_ref().unwrap().clone()
@@ -279,6 +306,9 @@ impl VersionSet {
 
     pub fn pick_compaction(&mut self) -> Option<Compaction> {
         assert!(self.current.is_some());
+        if self.opt.compaction_style == CompactionStyle::Universal {
+            return self.pick_universal_compaction();
+        }
         let current = self.current();
         let current = current.borrow();
 
@@ -315,6 +345,8 @@ impl VersionSet {
         }
 
         c.level = level;
+        c.output_level = level + 1;
+        c.max_file_size = self.opt.max_file_size_for_level(level + 1);
         c.input_version = self.current.clone();
 
         if level == 0 {
@@ -328,6 +360,63 @@ impl VersionSet {
         Some(c)
     }
 
+    /// pick_universal_compaction picks sorted runs to merge with universal compaction. Every L0
+    /// table is a sorted run, with higher file numbers denoting newer runs.
+    ///
+    /// Only windows of runs starting at the newest one are merged; this way, the output (which
+    /// has the highest file number) is still newer than all remaining runs.
+    fn pick_universal_compaction(&mut self) -> Option<Compaction> {
+        let current = self.current();
+        let current = current.borrow();
+        let mut runs = current.files[0].clone();
+        if runs.len() < self.opt.level0_file_num_compaction_trigger.max(2) {
+            return None;
+        }
+        runs.sort_by_key(|f| std::cmp::Reverse(f.borrow().num));
+
+        let size = |f: &FileMetaHandle| f.borrow().size;
+        let oldest = size(runs.last().unwrap());
+        let newer = total_size(runs[..runs.len() - 1].iter());
+        let width = if newer * 100 > oldest * self.opt.universal_max_size_amplification_percent {
+            // Reduce space amplification by merging all runs.
+            runs.len()
+        } else {
+            // Merge runs of similar size; otherwise just enough runs to get below the trigger.
+            let mut width = 1;
+            let mut window_size = size(&runs[0]);
+            while width < runs.len()
+                && size(&runs[width]) * 100 <= window_size * (100 + self.opt.universal_size_ratio)
+            {
+                window_size += size(&runs[width]);
+                width += 1;
+            }
+            if width < self.opt.universal_min_merge_width {
+                width = runs.len() + 2 - self.opt.level0_file_num_compaction_trigger.max(2);
+            }
+            width
+        };
+        log!(
+            self.opt.log,
+            "Universal compaction of {} out of {} runs",
+            width,
+            runs.len()
+        );
+
+        runs.truncate(width);
+        Some(self.universal_compaction(runs))
+    }
+
+    /// universal_compaction returns a compaction merging the given L0 tables into a new one.
+    fn universal_compaction(&self, inputs: Vec<FileMetaHandle>) -> Compaction {
+        let mut c = Compaction::new(&self.opt, 0, self.current.clone());
+        c.output_level = 0;
+        c.bottommost = inputs.len() == self.current().borrow().files[0].len();
+        // Every output table is a sorted run of its own.
+        c.max_file_size = usize::MAX;
+        c.inputs[0] = inputs;
+        c
+    }
+
     pub fn compact_range<'a, 'b>(
         &mut self,
         level: usize,
@@ -335,6 +424,16 @@ impl VersionSet {
         to: InternalKey<'b>,
     ) -> Option<Compaction> {
         assert!(self.current.is_some());
+        if self.opt.compaction_style == CompactionStyle::Universal {
+            // There is only a single level, which is compacted entirely.
+            let files = self.current().borrow().files[0].clone();
+            if level > 0 || files.len() < 2 {
+                return None;
+            }
+            let mut c = self.universal_compaction(files);
+            c.manual = true;
+            return Some(c);
+        }
         let mut inputs = self
             .current
             .as_ref()
@@ -349,7 +448,7 @@ impl VersionSet {
             let mut total = 0;
             for i in 0..inputs.len() {
                 total += inputs[i].borrow().size;
-                if total > self.opt.max_file_size {
+                if total > self.opt.max_file_size_for_level(level) {
                     inputs.truncate(i + 1);
                     break;
                 }
@@ -389,7 +488,7 @@ impl VersionSet {
             let expanded0_size = total_size(expanded0.iter());
             // ...if we picked up more files in the current level, and the total size is acceptable
             if expanded0.len() > compaction.num_inputs(0)
-                && (inputs1_size + expanded0_size) < 25 * self.opt.max_file_size
+                && (inputs1_size + expanded0_size) < 25 * self.opt.max_file_size_for_level(level)
             {
                 let (new_start, new_limit) = get_range(&self.cmp, expanded0.iter());
                 let expanded1 = current.overlapping_inputs(level + 1, &new_start, &new_limit);
@@ -531,13 +630,10 @@ impl VersionSet {
         for l in 0..NUM_LEVELS - 1 {
             let score: f64;
             if l == 0 {
-                score = v.files[l].len() as f64 / 4.0;
+                score =
+                    v.files[l].len() as f64 / self.opt.level0_file_num_compaction_trigger as f64;
             } else {
-                let mut max_bytes = 10.0 * f64::from(1 << 20);
-                for _ in 0..l - 1 {
-                    max_bytes *= 10.0;
-                }
-                score = total_size(v.files[l].iter()) as f64 / max_bytes;
+                score = total_size(v.files[l].iter()) as f64 / self.opt.max_bytes_for_level(l);
             }
             if let Some(ref mut b) = best_score {
                 if *b < score {
@@ -556,6 +652,87 @@ impl VersionSet {
     /// recover recovers the state of a LevelDB instance from the files on disk. If recover()
     /// returns true, the a manifest needs to be written eventually (using log_and_apply()).
     pub fn recover(&mut self) -> Result<bool> {
+        let (descfilename, current) = self.read_manifest()?;
+        // A new manifest needs to be written only if we don't reuse the existing one.
+        Ok(!self.reuse_manifest(&descfilename, &current))
+    }
+
+    /// recover_read_only is like recover(), but doesn't open the manifest for writing.
+    /// log_and_apply() must not be called afterwards.
+    pub fn recover_read_only(&mut self) -> Result<()> {
+        self.read_manifest().map(|_| ())
+    }
+
+    /// catch_up applies the records appended to the manifest since it was read by
+    /// recover_read_only() or the last call to catch_up(), and installs a new version if there
+    /// were any. If the CURRENT file names a new manifest, the state is rebuilt from it. Returns
+    /// true if a new version was installed.
+    pub fn catch_up(&mut self) -> Result<bool> {
+        let (path, applied) = match self.manifest_tail.clone() {
+            Some(tail) => tail,
+            None => return err(StatusCode::InvalidArgument, "no manifest has been read"),
+        };
+
+        let mut current = read_current_file(&self.opt.env, &self.dbname)?;
+        let len = current.len();
+        current.truncate(len - 1);
+        if self.dbname.join(&current) != path {
+            log!(self.opt.log, "New manifest {}, rebuilding state", current);
+            let v = Version::new(self.cache.clone(), self.opt.cmp.clone());
+            self.current = Some(share(v));
+            self.compaction_ptrs = Default::default();
+            self.read_manifest()?;
+            return Ok(true);
+        }
+
+        let mut builder = Builder::new();
+        let mut records = 0;
+        {
+            let mut descfile = self.opt.env.open_sequential_file(&path)?;
+            let mut logreader = LogReader::new(&mut descfile, true);
+            // A record that is still being written is read again at the next call.
+            logreader.ignore_truncated_tail();
+            let mut buf = Vec::new();
+            loop {
+                let size = logreader.read(&mut buf)?;
+                if size == 0 {
+                    break;
+                }
+                records += 1;
+                if records <= applied {
+                    continue;
+                }
+                let edit = VersionEdit::decode_from(&buf)?;
+                builder.apply(&edit, &mut self.compaction_ptrs);
+                if let Some(ln) = edit.log_number {
+                    self.log_num = ln;
+                }
+                if let Some(nfn) = edit.next_file_number {
+                    self.next_file_num = nfn + 1;
+                }
+                if let Some(ls) = edit.last_seq {
+                    self.last_seq = ls;
+                }
+                if let Some(pln) = edit.prev_log_number {
+                    self.prev_log_num = pln;
+                }
+            }
+        }
+        if records <= applied {
+            return Ok(false);
+        }
+
+        let mut v = Version::new(self.cache.clone(), self.opt.cmp.clone());
+        builder.save_to(&self.cmp, self.current.as_ref().unwrap(), &mut v);
+        self.finalize(&mut v);
+        self.add_version(v);
+        self.manifest_tail = Some((path, records));
+        Ok(true)
+    }
+
+    /// read_manifest reads the manifest named by the CURRENT file and installs the version
+    /// described by it. It returns the manifest's path and file name.
+    fn read_manifest(&mut self) -> Result<(PathBuf, PathBuf)> {
         assert!(self.current.is_some());
 
         let mut current = read_current_file(&self.opt.env, &self.dbname)?;
@@ -581,12 +758,34 @@ impl VersionSet {
             let mut next_file_number = None;
             let mut last_seq = None;
 
+            let mut records = 0;
             let mut buf = Vec::new();
-            while let Ok(size) = logreader.read(&mut buf) {
+            loop {
+                let size = match logreader.read(&mut buf) {
+                    Ok(size) => size,
+                    Err(e) if self.opt.paranoid_checks => return Err(e),
+                    Err(e) => {
+                        log!(self.opt.log, Warn, "Error reading manifest: {}", e);
+                        break;
+                    }
+                };
                 if size == 0 {
                     break;
                 }
+                records += 1;
                 let edit = VersionEdit::decode_from(&buf)?;
+                if let Some(name) = edit.comparator_name() {
+                    if name != self.opt.cmp.id() {
+                        return err(
+                            StatusCode::InvalidArgument,
+                            &format!(
+                                "comparator {} does not match existing comparator {}",
+                                self.opt.cmp.id(),
+                                name
+                            ),
+                        );
+                    }
+                }
                 builder.apply(&edit, &mut self.compaction_ptrs);
                 if let Some(ln) = edit.log_number {
                     log_number = Some(ln);
@@ -633,6 +832,7 @@ impl VersionSet {
             } else {
                 self.prev_log_num = 0;
             }
+            self.manifest_tail = Some((descfilename.clone(), records));
         }
 
         let mut v = Version::new(self.cache.clone(), self.opt.cmp.clone());
@@ -650,9 +850,7 @@ impl VersionSet {
             self.prev_log_num,
             self.last_seq
         );
-
-        // A new manifest needs to be written only if we don't reuse the existing one.
-        Ok(!self.reuse_manifest(&descfilename, &current))
+        Ok((descfilename, current.to_owned()))
     }
 
     /// reuse_manifest checks whether the current manifest can be reused.
@@ -676,7 +874,8 @@ impl VersionSet {
                 return false;
             }
             if let Ok(size) = self.opt.env.size_of(Path::new(current_manifest_path)) {
-                if size > self.opt.max_file_size {
+                // A manifest is rewritten once it's larger than a table of the first level.
+                if size > self.opt.max_file_size_for_level(1) {
                     return false;
                 }
             } else {
@@ -712,12 +911,13 @@ impl VersionSet {
                 // Add individual iterators for L0 tables.
                 for fi in 0..c.num_inputs(i) {
                     let f = &c.inputs[i][fi];
-                    let s = self.cache.borrow_mut().get_table(f.borrow().num);
+                    let s = self.cache.borrow_mut().get_file_table(&f.borrow(), 0);
                     if let Ok(tbl) = s {
-                        iters.push(Box::new(tbl.iter()));
+                        iters.push(Box::new(tbl.sequential_iter()));
                     } else {
                         log!(
                             self.opt.log,
+                            Warn,
                             "error opening table {}: {}",
                             f.borrow().num,
                             s.err().unwrap()
@@ -726,11 +926,13 @@ impl VersionSet {
                 }
             } else {
                 // Create concatenating iterator higher levels.
-                iters.push(Box::new(new_version_iter(
+                let iter = new_version_iter(
                     c.inputs[i].clone(),
+                    c.level + i,
                     self.cache.clone(),
                     self.opt.cmp.clone(),
-                )));
+                );
+                iters.push(Box::new(iter.sequential()));
             }
         }
         assert!(iters.len() <= cap);
@@ -972,6 +1174,7 @@ mod tests {
     use super::*;
     use crate::cmp::DefaultCmp;
     use crate::key_types::LookupKey;
+    use crate::options;
     use crate::test_util::LdbIteratorIter;
     use crate::types::FileMetaData;
     use crate::version::testutil::make_version;
@@ -1070,6 +1273,31 @@ mod tests {
         assert_eq!(21, v2.files[1][3].borrow().num);
     }
 
+    #[test]
+    fn test_version_set_recover_comparator_mismatch() {
+        let opt = options::for_test();
+        let mut ve = VersionEdit::new();
+        ve.set_comparator_name("other.Comparator");
+        ve.set_log_num(1);
+        ve.set_next_file(2);
+        ve.set_last_seq(0);
+
+        let manifest = manifest_file_name("db", 1);
+        let mffile = opt.env.open_writable_file(Path::new(&manifest)).unwrap();
+        let mut lw = LogWriter::new(mffile);
+        lw.add_record(&ve.encode()).unwrap();
+        lw.flush().unwrap();
+        set_current_file(opt.env.as_ref(), "db", 1).unwrap();
+
+        let mut vs = VersionSet::new(
+            "db",
+            opt.clone(),
+            share(TableCache::new("db", opt.clone(), 100)),
+        );
+        let e = vs.recover().err().unwrap();
+        assert_eq!(StatusCode::InvalidArgument, e.code);
+    }
+
     #[test]
     fn test_version_set_log_and_apply() {
         let (_, opt) = make_version();
@@ -1200,6 +1428,72 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_version_set_finalize() {
+        let (mut v, mut opt) = make_version();
+        let l0_files = v.files[0].len();
+        let cache = share(TableCache::new("db", opt.clone(), 100));
+
+        opt.level0_file_num_compaction_trigger = 1;
+        opt.max_bytes_for_level_base = 1 << 30;
+        VersionSet::new("db", opt.clone(), cache.clone()).finalize(&mut v);
+        assert_eq!(Some(0), v.compaction_level);
+        assert_eq!(Some(l0_files as f64), v.compaction_score);
+
+        opt.level0_file_num_compaction_trigger = 100;
+        opt.max_bytes_for_level_base = 1;
+        VersionSet::new("db", opt.clone(), cache).finalize(&mut v);
+        assert_eq!(Some(1), v.compaction_level);
+        assert!(v.compaction_score.unwrap() > 1.0);
+
+        assert_eq!(10.0, opt.max_bytes_for_level(2));
+        opt.target_file_size_multiplier = 2;
+        assert_eq!(opt.max_file_size, opt.max_file_size_for_level(1));
+        assert_eq!(4 * opt.max_file_size, opt.max_file_size_for_level(3));
+    }
+
+    #[test]
+    fn test_version_set_pick_universal_compaction() {
+        let mut opt = options::for_test();
+        opt.compaction_style = CompactionStyle::Universal;
+        let cache = share(TableCache::new("db", opt.clone(), 100));
+
+        // Sizes of the runs, from oldest to newest.
+        let pick = |sizes: &[usize]| {
+            let mut v = Version::new(cache.clone(), opt.cmp.clone());
+            for (i, size) in sizes.iter().enumerate() {
+                let f = FileMetaData {
+                    num: i as u64 + 1,
+                    size: *size,
+                    smallest: LookupKey::new(b"aaa", 1).internal_key().to_vec(),
+                    largest: LookupKey::new(b"zzz", 1).internal_key().to_vec(),
+                    ..Default::default()
+                };
+                v.files[0].push(share(f));
+            }
+            let mut vs = VersionSet::new("db", opt.clone(), cache.clone());
+            vs.add_version(v);
+            vs.pick_compaction().map(|c| {
+                assert_eq!(0, c.output_level());
+                assert!(!c.is_trivial_move());
+                let nums: Vec<_> = c.inputs[0].iter().map(|f| f.borrow().num).collect();
+                (nums, c.bottommost)
+            })
+        };
+
+        // Below trigger.
+        assert_eq!(None, pick(&[100, 10, 1]));
+        // Runs of similar size.
+        assert_eq!(Some((vec![5, 4, 3], false)), pick(&[100, 10, 1, 1, 1]));
+        // Size amplification.
+        assert_eq!(Some((vec![5, 4, 3, 2, 1], true)), pick(&[5, 10, 1, 1, 1]));
+        // No similar runs; merge enough to get below the trigger.
+        assert_eq!(
+            Some((vec![5, 4, 3], false)),
+            pick(&[10000, 1000, 100, 10, 1])
+        );
+    }
+
     /// iterator_properties tests that it contains len elements and that they are ordered in
     /// ascending order by cmp.
     fn iterator_properties<It: LdbIterator>(mut it: It, len: usize, cmp: Rc<Box<dyn Cmp>>) {
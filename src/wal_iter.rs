@@ -0,0 +1,107 @@
+//! wal_iter contains WalIterator, which reads the write batches stored in a database's log files.
+//! It is used for change data capture via `DB::get_updates_since()`.
+
+use crate::db_impl::log_file_name;
+use crate::env::Env;
+use crate::error::{err, Result, StatusCode};
+use crate::log::LogReader;
+use crate::types::{FileNum, SequenceNumber};
+use crate::write_batch::WriteBatch;
+
+use std::collections::VecDeque;
+use std::io::Read;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// WalIterator returns all write batches containing entries with a sequence number at or after a
+/// given one, in the order they were written. Every item consists of the batch's first sequence
+/// number and the batch itself.
+///
+/// Only log files present when the iterator is created are read. Batches from log files that have
+/// been deleted already are missing; callers can detect this by checking whether the first
+/// returned sequence number is larger than the requested one.
+pub struct WalIterator {
+    env: Rc<Box<dyn Env>>,
+    db: PathBuf,
+    logs: VecDeque<FileNum>,
+    current: Option<(FileNum, LogReader<Box<dyn Read>>)>,
+    since: SequenceNumber,
+    scratch: Vec<u8>,
+}
+
+impl WalIterator {
+    /// Create a new iterator over the log files `logs` (which must be sorted) in the database
+    /// directory `db`.
+    pub fn new(
+        env: Rc<Box<dyn Env>>,
+        db: PathBuf,
+        logs: Vec<FileNum>,
+        since: SequenceNumber,
+    ) -> WalIterator {
+        WalIterator {
+            env,
+            db,
+            logs: logs.into(),
+            current: None,
+            since,
+            scratch: vec![],
+        }
+    }
+
+    /// read_next returns the next batch from any log file, or None if all files are exhausted.
+    fn read_next(&mut self) -> Result<Option<WriteBatch>> {
+        loop {
+            if self.current.is_none() {
+                let num = match self.logs.pop_front() {
+                    Some(num) => num,
+                    None => return Ok(None),
+                };
+                let f = self
+                    .env
+                    .open_sequential_file(&log_file_name(&self.db, num))?;
+                self.current = Some((num, LogReader::new(f, true)));
+            }
+
+            let (num, reader) = self.current.as_mut().unwrap();
+            let len = reader.read(&mut self.scratch)?;
+            if len == 0 {
+                self.current = None;
+                continue;
+            }
+            if len < 12 {
+                return err(
+                    StatusCode::Corruption,
+                    &format!("record shorter than 12B in log file {:06}", num),
+                );
+            }
+            let mut batch = WriteBatch::new();
+            batch.set_contents(&self.scratch);
+            return Ok(Some(batch));
+        }
+    }
+}
+
+impl Iterator for WalIterator {
+    type Item = Result<(SequenceNumber, WriteBatch)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.read_next() {
+                Ok(Some(batch)) => {
+                    let last = batch.sequence() + batch.count() as u64;
+                    if batch.count() == 0 || last <= self.since {
+                        continue;
+                    }
+                    return Some(Ok((batch.sequence(), batch)));
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    // Don't return anything after an error.
+                    self.logs.clear();
+                    self.current = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
@@ -65,7 +65,8 @@ impl WriteBatch {
         self.entries.clear()
     }
 
-    fn byte_size(&self) -> usize {
+    /// Returns the size of the encoded batch.
+    pub fn byte_size(&self) -> usize {
         self.entries.len()
     }
 